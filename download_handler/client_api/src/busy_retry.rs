@@ -0,0 +1,70 @@
+use std::thread;
+use std::time::Duration;
+
+use crate::is_server_busy;
+
+pub const DEFAULT_BUSY_RETRY_ATTEMPTS: u32 = 4;
+pub const DEFAULT_BUSY_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+pub const DEFAULT_BUSY_RETRY_MAX_WAIT: Duration = Duration::from_secs(15);
+
+/// How a transfer turned away with `BUSY` is retried. Only that reply is
+/// retried: a refused connection or a transfer failing halfway is returned
+/// straight away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusyRetry {
+    /// Tries in total, the first one included.
+    pub attempts: u32,
+    /// Delay before the second try; doubled after every further one.
+    pub backoff: Duration,
+    /// Upper bound on the time spent waiting between tries. A delay that would
+    /// go past it is not taken and the busy error is returned instead.
+    pub max_wait: Duration,
+}
+
+impl Default for BusyRetry {
+    fn default() -> Self {
+        Self {
+            attempts: DEFAULT_BUSY_RETRY_ATTEMPTS,
+            backoff: DEFAULT_BUSY_RETRY_BACKOFF,
+            max_wait: DEFAULT_BUSY_RETRY_MAX_WAIT,
+        }
+    }
+}
+
+impl BusyRetry {
+    /// Fails on the first `BUSY`, for callers that retry on their own.
+    pub fn disabled() -> Self {
+        Self {
+            attempts: 1,
+            backoff: Duration::ZERO,
+            max_wait: Duration::ZERO,
+        }
+    }
+}
+
+/// Runs `op` until it gets past a busy server or `retry` is used up.
+/// `on_busy` is told the delay before each retry is slept.
+pub(crate) fn retry_on_busy<T, B>(
+    retry: &BusyRetry,
+    mut on_busy: B,
+    mut op: impl FnMut() -> std::io::Result<T>,
+) -> std::io::Result<T>
+where
+    B: FnMut(Duration),
+{
+    let mut backoff = retry.backoff;
+    let mut waited = Duration::ZERO;
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(e) if is_server_busy(&e) && attempt < retry.attempts && waited + backoff <= retry.max_wait => {
+                on_busy(backoff);
+                thread::sleep(backoff);
+                waited += backoff;
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::io::Read;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Running CRC-32 (IEEE), the same one zip and PNG use.
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { state: 0xFFFF_FFFF }
+    }
+
+    /// Continues from the CRC of the bytes before, as returned by `finish`.
+    pub fn resume(crc: u32) -> Self {
+        Self { state: !crc }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state = CRC32_TABLE[((self.state ^ byte as u32) & 0xFF) as usize] ^ (self.state >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+/// Checksums the first `length` bytes of `reader`; fails if it is shorter.
+pub fn crc32_prefix<R: Read>(reader: R, length: u64) -> std::io::Result<u32> {
+    let mut prefix = reader.take(length);
+    let mut crc = Crc32::new();
+    let mut buffer = [0u8; 8192];
+    let mut read = 0u64;
+    loop {
+        let n = prefix.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        crc.update(&buffer[..n]);
+        read += n as u64;
+    }
+    if read != length {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("Expected {} bytes to checksum, found {}", length, read),
+        ));
+    }
+    Ok(crc.finish())
+}
@@ -0,0 +1,190 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/// A server address starting with this is a Unix domain socket path
+/// (`unix:/tmp/download_handler.sock`) instead of `host:port`.
+pub const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// How long each resolved address of a string `server_addr` is tried.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Server address for an already resolved `addr` that gives up connecting after
+/// `timeout`, to pass wherever a `server_addr` is taken. Unlike a string address
+/// nothing is resolved, so the address family is exactly that of `addr`.
+pub fn connect_addr(addr: SocketAddr, timeout: Duration) -> TcpEndpoint {
+    TcpEndpoint { addr, timeout }
+}
+
+/// See [`connect_addr`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpEndpoint {
+    pub addr: SocketAddr,
+    pub timeout: Duration,
+}
+
+/// Server address for the Unix domain socket at `path`, to pass wherever a
+/// `server_addr` is taken.
+#[cfg(unix)]
+pub fn unix_socket_addr(path: &std::path::Path) -> String {
+    format!("{}{}", UNIX_SOCKET_PREFIX, path.display())
+}
+
+/// A connected byte stream to the server. The command framing is written against
+/// this instead of a concrete socket.
+pub trait Transport: Read + Write {
+    /// Printable server endpoint.
+    fn peer_label(&self) -> String;
+}
+
+/// Where the client functions connect to, passed wherever a `server_addr` is
+/// taken. Each call opens its connections through `connect`, so another
+/// transport (a QUIC stream, say) only needs an impl with its own stream type.
+pub trait Connect {
+    type Stream: Transport;
+
+    fn connect(&self) -> std::io::Result<Self::Stream>;
+}
+
+/// `host:port`, or a Unix domain socket path after [`UNIX_SOCKET_PREFIX`].
+impl Connect for str {
+    type Stream = Connection;
+
+    fn connect(&self) -> std::io::Result<Connection> {
+        Connection::connect(self)
+    }
+}
+
+impl Connect for String {
+    type Stream = Connection;
+
+    fn connect(&self) -> std::io::Result<Connection> {
+        Connection::connect(self)
+    }
+}
+
+impl Connect for TcpEndpoint {
+    type Stream = TcpStream;
+
+    fn connect(&self) -> std::io::Result<TcpStream> {
+        TcpStream::connect_timeout(&self.addr, self.timeout.max(Duration::from_millis(1)))
+    }
+}
+
+impl Transport for TcpStream {
+    fn peer_label(&self) -> String {
+        self.peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|_| "unknown".to_string())
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn peer_label(&self) -> String {
+        match self.peer_addr() {
+            Ok(addr) => match addr.as_pathname() {
+                Some(path) => format!("{}{}", UNIX_SOCKET_PREFIX, path.display()),
+                None => format!("{}(unnamed)", UNIX_SOCKET_PREFIX),
+            },
+            Err(_) => format!("{}unknown", UNIX_SOCKET_PREFIX),
+        }
+    }
+}
+
+/// The stream behind a string address: TCP, or a Unix domain socket.
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    pub(crate) fn connect(server_addr: &str) -> std::io::Result<Self> {
+        match server_addr.strip_prefix(UNIX_SOCKET_PREFIX) {
+            #[cfg(unix)]
+            Some(path) => Ok(Connection::Unix(UnixStream::connect(path)?)),
+            #[cfg(not(unix))]
+            Some(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Unix domain sockets are not supported on this platform"
+            )),
+            None => Ok(Connection::Tcp(connect_tcp(server_addr)?)),
+        }
+    }
+}
+
+/// Tries every address `server_addr` resolves to, in order, each for at most
+/// `DEFAULT_CONNECT_TIMEOUT`.
+fn connect_tcp(server_addr: &str) -> std::io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in server_addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, DEFAULT_CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} did not resolve to any address", server_addr)
+        )
+    }))
+}
+
+impl Transport for Connection {
+    fn peer_label(&self) -> String {
+        match self {
+            Connection::Tcp(stream) => stream.peer_label(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.peer_label(),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn connect_addr_reaches_exactly_that_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let stream = connect_addr(addr, Duration::from_secs(1)).connect().unwrap();
+        assert_eq!(stream.peer_label(), addr.to_string());
+        // A zero timeout still makes a (short) attempt instead of failing outright.
+        assert!(connect_addr(addr, Duration::ZERO).connect().is_ok());
+    }
+}
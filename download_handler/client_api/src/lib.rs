@@ -1,38 +1,422 @@
-use std::fs::File;
-use std::io::{Read, Write};
-use std::net::TcpStream;
-use std::path::Path;
+use std::cell::Cell;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::time::{Instant, Duration};
 use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
 use serde::Serialize;
 
+mod busy_retry;
+mod checksum;
+mod connection;
+mod mime;
+mod permissions;
+mod speed;
+mod sync;
+
+pub use busy_retry::{BusyRetry, DEFAULT_BUSY_RETRY_ATTEMPTS, DEFAULT_BUSY_RETRY_BACKOFF, DEFAULT_BUSY_RETRY_MAX_WAIT};
+pub use checksum::{crc32_prefix, Crc32};
+#[cfg(unix)]
+pub use connection::unix_socket_addr;
+pub use connection::{connect_addr, Connect, Connection, TcpEndpoint, Transport, DEFAULT_CONNECT_TIMEOUT};
+pub use connection::UNIX_SOCKET_PREFIX;
+pub use mime::content_type_for;
+pub use permissions::{apply_mode, file_mode, TRANSFERABLE_MODE_MASK};
+pub use speed::{set_speed_window, speed_window, SpeedSmoother, SpeedWindow, DEFAULT_SPEED_SMOOTHING, DEFAULT_SPEED_WINDOW};
+pub use sync::{sync_directory, SyncFileResult, SyncOptions, SyncOutcome, SyncSummary};
+
+const TYPED_LIST_VERSION_COUNTED: u8 = 1;
+const TYPED_LIST_VERSION_STREAMED: u8 = 2;
+const TYPED_LIST_VERSION_WITH_STATUS: u8 = 3;
+const LIST_FRAME_END: u8 = 0;
+const LIST_FRAME_ENTRY: u8 = 1;
+const STATUS_PREFIX_MISMATCH: u8 = 2;
+/// First byte of the `BUSY\n` reply a server at its connection limit sends.
+const SERVER_BUSY: u8 = b'B';
+const MOVE_FLAG_OVERWRITE: u8 = 1;
+const CAP_FILE_MODES: u32 = 1 << 0;
+const CAP_RESUME: u32 = 1 << 1;
+const CAP_VERIFIED_RESUME: u32 = 1 << 2;
+const CAP_UPLOAD_ACK_CHECKSUM: u32 = 1 << 3;
+const CAP_HEAD: u32 = 1 << 4;
+const CAP_MOVE: u32 = 1 << 5;
+const CAP_TRANSFERS: u32 = 1 << 6;
+const CAP_INTEGRITY_REPORT: u32 = 1 << 7;
+const CAP_DEDUP: u32 = 1 << 8;
+const CAP_READ_ONLY: u32 = 1 << 9;
+const CAP_PING: u32 = 1 << 10;
+
 #[derive(Debug, Clone, Serialize)]
 pub struct RemoteFileInfo {
+    /// Printable name. If the server's name isn't valid UTF-8 this is a lossy
+    /// rendering and `raw_name` holds the exact bytes.
     pub name: String,
     pub size_mb: f64,
+    /// Exact size as listed, for [`download_file_expecting_size`].
+    pub size_bytes: u64,
+    pub content_type: Option<String>,
+    /// Set only when the name was not valid UTF-8; download such files with
+    /// [`download_file_by_raw_name`].
+    pub raw_name: Option<Vec<u8>>,
+}
+
+impl RemoteFileInfo {
+    /// The name exactly as the server stores it.
+    pub fn wire_name(&self) -> &[u8] {
+        self.raw_name.as_deref().unwrap_or(self.name.as_bytes())
+    }
+}
+
+fn server_busy() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::ResourceBusy, "Server at capacity, try again later")
+}
+
+/// Whether `e` came from a server that turned the request away because it is
+/// at its connection limit, as opposed to being unreachable or failing mid-transfer.
+pub fn is_server_busy(e: &std::io::Error) -> bool {
+    e.kind() == std::io::ErrorKind::ResourceBusy
+}
+
+/// The server's copy no longer has the size it was listed with, so it was
+/// most likely replaced in between.
+#[derive(Debug)]
+struct FileChanged {
+    listed: u64,
+    actual: u64,
+}
+
+impl std::fmt::Display for FileChanged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "File changed on server: listed with {} bytes, now {} bytes; refresh the file list",
+            self.listed, self.actual
+        )
+    }
+}
+
+impl std::error::Error for FileChanged {}
+
+/// Whether `e` is [`download_file_expecting_size`] refusing a file that changed
+/// since it was listed.
+pub fn is_file_changed(e: &std::io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<FileChanged>())
+}
+
+/// What a received download is checked against, where known.
+#[derive(Debug, Clone, Copy, Default)]
+struct Expected {
+    crc: Option<u32>,
+    size: Option<u64>,
+}
+
+/// State of the server's upload directory, as reported alongside the listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListingStatus {
+    /// The directory did not exist and was created by this request.
+    Created,
+    Empty,
+    Populated,
+}
+
+impl ListingStatus {
+    fn from_wire(status: u8) -> std::io::Result<Self> {
+        match status {
+            0 => Ok(ListingStatus::Created),
+            1 => Ok(ListingStatus::Empty),
+            2 => Ok(ListingStatus::Populated),
+            other => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Unexpected listing status {}", other)
+            )),
+        }
+    }
 }
 
-pub fn fetch_available_files(server_addr: &str) -> std::io::Result<Vec<RemoteFileInfo>> {
-    let mut stream = TcpStream::connect(server_addr)?;
-    stream.write_all(&[b'L'])?;
+#[derive(Debug, Clone, Serialize)]
+pub struct RemoteListing {
+    pub status: ListingStatus,
+    pub files: Vec<RemoteFileInfo>,
+}
+
+pub fn fetch_available_files(server_addr: &(impl Connect + ?Sized)) -> std::io::Result<Vec<RemoteFileInfo>> {
+    fetch_listing(server_addr).map(|listing| listing.files)
+}
+
+/// Fetches the listing together with the directory status, so callers can tell
+/// "no files yet" apart from a failed request.
+pub fn fetch_listing(server_addr: &(impl Connect + ?Sized)) -> std::io::Result<RemoteListing> {
+    let mut files = Vec::new();
+    let status = stream_listing(server_addr, |file| files.push(file))?;
+    Ok(RemoteListing { status, files })
+}
+
+/// Like [`fetch_available_files`], but hands each file to `on_file` as soon as it
+/// arrives instead of waiting for the whole directory scan.
+pub fn stream_available_files<F>(server_addr: &(impl Connect + ?Sized), on_file: F) -> std::io::Result<()>
+where
+    F: FnMut(RemoteFileInfo),
+{
+    stream_listing(server_addr, on_file).map(|_| ())
+}
+
+/// Servers that predate a listing command close the connection on it, so each
+/// EOF before the first file falls back to the next older command. Statuses for
+/// older servers are inferred from whether any file was delivered.
+fn stream_listing<F>(server_addr: &(impl Connect + ?Sized), mut on_file: F) -> std::io::Result<ListingStatus>
+where
+    F: FnMut(RemoteFileInfo),
+{
+    let delivered = Cell::new(0usize);
+    let mut counting = |file| {
+        delivered.set(delivered.get() + 1);
+        on_file(file);
+    };
+
+    let mut result = stream_typed_listing(server_addr, true, &mut counting);
+    for fallback in 0..2 {
+        match result {
+            Err(ref e) if is_unsupported_command(e) && delivered.get() == 0 => {
+                result = if fallback == 0 {
+                    stream_typed_listing(server_addr, false, &mut counting)
+                } else {
+                    fetch_legacy_listing(server_addr, &mut counting).map(|_| None)
+                };
+            }
+            _ => break,
+        }
+    }
+
+    match result? {
+        Some(status) => Ok(status),
+        None if delivered.get() == 0 => Ok(ListingStatus::Empty),
+        None => Ok(ListingStatus::Populated),
+    }
+}
+
+/// An older server drops the connection on an unknown command; if the command
+/// carried extra bytes it never read, the close shows up as a reset (or a broken
+/// pipe while still sending).
+fn is_unsupported_command(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::BrokenPipe
+    )
+}
+
+fn stream_typed_listing<F>(server_addr: &(impl Connect + ?Sized), negotiate: bool, on_file: &mut F) -> std::io::Result<Option<ListingStatus>>
+where
+    F: FnMut(RemoteFileInfo),
+{
+    let mut stream = server_addr.connect()?;
+    if negotiate {
+        stream.write_all(&[b'V', TYPED_LIST_VERSION_WITH_STATUS])?;
+    } else {
+        stream.write_all(b"T")?;
+    }
+
+    let mut status = None;
+    match stream.read_u8()? {
+        SERVER_BUSY => return Err(server_busy()),
+        TYPED_LIST_VERSION_COUNTED => {
+            let count = stream.read_u16::<BigEndian>()? as usize;
+            for _ in 0..count {
+                if let Some(file) = read_typed_entry(&mut stream)? {
+                    on_file(file);
+                }
+            }
+        }
+        version @ (TYPED_LIST_VERSION_STREAMED | TYPED_LIST_VERSION_WITH_STATUS) => {
+            if version == TYPED_LIST_VERSION_WITH_STATUS {
+                status = Some(ListingStatus::from_wire(stream.read_u8()?)?);
+            }
+            loop {
+                match stream.read_u8()? {
+                    LIST_FRAME_END => break,
+                    LIST_FRAME_ENTRY => {
+                        if let Some(file) = read_typed_entry(&mut stream)? {
+                            on_file(file);
+                        }
+                    }
+                    other => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("Unexpected listing frame {}", other)
+                        ));
+                    }
+                }
+            }
+        }
+        version => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Unsupported listing version {}", version)
+            ));
+        }
+    }
+    Ok(status)
+}
+
+fn read_typed_entry(stream: &mut impl Transport) -> std::io::Result<Option<RemoteFileInfo>> {
+    let (name, raw_name, size_bytes) = read_raw_listing_entry(stream)?;
+    let type_len = stream.read_u8()? as usize;
+    let mut type_buf = vec![0u8; type_len];
+    stream.read_exact(&mut type_buf)?;
+    let content_type = String::from_utf8(type_buf).ok().filter(|t| !t.is_empty());
+    if name.is_empty() {
+        return Ok(None);
+    }
+    let size_mb = size_bytes as f64 / (1024.0 * 1024.0);
+    let content_type = content_type.or_else(|| content_type_for(&name).map(str::to_string));
+    Ok(Some(RemoteFileInfo { name, size_mb, size_bytes, content_type, raw_name }))
+}
+
+fn fetch_legacy_listing<F>(server_addr: &(impl Connect + ?Sized), on_file: &mut F) -> std::io::Result<()>
+where
+    F: FnMut(RemoteFileInfo),
+{
+    let mut stream = server_addr.connect()?;
+    stream.write_all(b"L")?;
 
     let count = stream.read_u16::<BigEndian>()? as usize;
-    let mut files = Vec::with_capacity(count);
     for _ in 0..count {
-        let name_len = stream.read_u16::<BigEndian>()? as usize;
-        let mut buf = vec![0u8; name_len];
-        stream.read_exact(&mut buf)?;
-        let name = String::from_utf8(buf).unwrap_or_default();
-        let size_bytes = stream.read_u64::<BigEndian>()?;
+        let (name, raw_name, size_bytes) = read_raw_listing_entry(&mut stream)?;
         if !name.is_empty() {
             let size_mb = size_bytes as f64 / (1024.0 * 1024.0);
-            files.push(RemoteFileInfo { name, size_mb });
+            let content_type = content_type_for(&name).map(str::to_string);
+            on_file(RemoteFileInfo { name, size_mb, size_bytes, content_type, raw_name });
         }
     }
-    Ok(files)
+    Ok(())
 }
 
-pub fn upload_file<F>(path: &Path, server_addr: &str, mut on_progress: F) -> std::io::Result<()>
+fn read_listing_entry(stream: &mut impl Transport) -> std::io::Result<(String, u64)> {
+    let (name, _, size_bytes) = read_raw_listing_entry(stream)?;
+    Ok((name, size_bytes))
+}
+
+/// Like [`read_listing_entry`], also returning the name's bytes when they are
+/// not valid UTF-8 (in which case the name is a lossy rendering of them).
+fn read_raw_listing_entry(stream: &mut impl Transport) -> std::io::Result<(String, Option<Vec<u8>>, u64)> {
+    let name_len = stream.read_u16::<BigEndian>()? as usize;
+    let mut buf = vec![0u8; name_len];
+    stream.read_exact(&mut buf)?;
+    let (name, raw_name) = match String::from_utf8(buf) {
+        Ok(name) => (name, None),
+        Err(e) => (String::from_utf8_lossy(e.as_bytes()).into_owned(), Some(e.into_bytes())),
+    };
+    let size_bytes = stream.read_u64::<BigEndian>()?;
+    Ok((name, raw_name, size_bytes))
+}
+
+/// Reads a `\n`-terminated status line without consuming anything after it, so
+/// the connection can be reused for the next command.
+fn read_response_line(stream: &mut impl Transport) -> std::io::Result<String> {
+    let mut line = Vec::new();
+    loop {
+        let byte = match stream.read_u8() {
+            Ok(byte) => byte,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && !line.is_empty() => break,
+            Err(e) => return Err(e),
+        };
+        if byte == b'\n' {
+            break;
+        }
+        line.push(byte);
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// Newer servers answer `OK <stored size> [<crc32 hex>]`; check those against
+/// what was sent. A bare `OK` from an older server is taken at its word.
+/// Returns the checksum when the server confirmed it.
+fn verify_upload_ack(ack: &str, sent_bytes: u64, sent_crc: u32) -> std::io::Result<Option<u32>> {
+    let mut fields = ack.split_whitespace().skip(1);
+
+    if let Some(size) = fields.next().and_then(|field| field.parse::<u64>().ok())
+        && size != sent_bytes
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Server stored {} bytes, sent {} bytes", size, sent_bytes)
+        ));
+    }
+
+    let confirmed_crc = fields.next().and_then(|field| u32::from_str_radix(field, 16).ok());
+    if let Some(crc) = confirmed_crc
+        && crc != sent_crc
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Server checksum {:08x} does not match sent data {:08x}", crc, sent_crc)
+        ));
+    }
+    Ok(confirmed_crc)
+}
+
+/// A busy or read-only server replies before the upload body is through and
+/// then closes, so a failed write may still have that reply waiting to be read.
+fn busy_reply_or(stream: &mut impl Transport, write_error: std::io::Error) -> std::io::Error {
+    match read_response_line(stream) {
+        Ok(line) if line.trim() == "BUSY" => server_busy(),
+        Ok(line) if line.starts_with("ERROR") => {
+            std::io::Error::other(format!("Server rejected file: {}", line.trim()))
+        }
+        _ => write_error,
+    }
+}
+
+/// On Unix the file's permission bits are sent along (setuid and friends are
+/// dropped); servers whose capabilities don't include modes get a plain
+/// upload instead. A busy server is retried as [`BusyRetry::default`] says.
+pub fn upload_file<F>(path: &Path, server_addr: &(impl Connect + ?Sized), on_progress: F) -> std::io::Result<()>
+where
+    F: FnMut(f64, f64, f64),
+{
+    upload_file_with_retry(path, server_addr, &BusyRetry::default(), print_busy_retry, on_progress).map(|_| ())
+}
+
+fn print_busy_retry(delay: Duration) {
+    println!("Server busy, retrying in {:.1}s", delay.as_secs_f64());
+}
+
+/// Same as [`upload_file`] with an explicit busy policy; `on_busy` gets the
+/// delay before every retry so a UI can say why nothing is moving. Returns the
+/// CRC-32 of the upload when the server's ack confirmed it.
+pub fn upload_file_with_retry<F, B>(
+    path: &Path,
+    server_addr: &(impl Connect + ?Sized),
+    retry: &BusyRetry,
+    on_busy: B,
+    mut on_progress: F,
+) -> std::io::Result<Option<u32>>
+where
+    F: FnMut(f64, f64, f64),
+    B: FnMut(Duration),
+{
+    busy_retry::retry_on_busy(retry, on_busy, || upload_once(path, server_addr, &mut on_progress))
+}
+
+fn upload_once<F>(path: &Path, server_addr: &(impl Connect + ?Sized), on_progress: &mut F) -> std::io::Result<Option<u32>>
+where
+    F: FnMut(f64, f64, f64),
+{
+    let mode = permissions::file_mode(path)?;
+    if mode == 0 {
+        return send_upload(path, server_addr, None, on_progress);
+    }
+    // Asked up front rather than guessed from a failed `A`: a reset mid-upload
+    // must not be mistaken for a server that doesn't know the command.
+    if fetch_capabilities(server_addr)?.supports_file_modes() {
+        send_upload(path, server_addr, Some(mode), on_progress)
+    } else {
+        println!("Server does not accept file modes, uploading {:?} without them", path);
+        send_upload(path, server_addr, None, on_progress)
+    }
+}
+
+fn send_upload<F>(path: &Path, server_addr: &(impl Connect + ?Sized), mode: Option<u32>, on_progress: &mut F) -> std::io::Result<Option<u32>>
 where
     F: FnMut(f64, f64, f64),
 {
@@ -43,50 +427,55 @@ where
     let file_name_full = path.file_name().and_then(|s| s.to_str()).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Bad file name"))?;
     let file_name_len = file_name_full.len();
     if file_name_len > 4096 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        return Err(std::io::Error::other(
             format!("You have a very long file name, len = {}", file_name_len)
         ));
     }
     
     let file_name = file_name_full.as_bytes().to_owned();
 
-    let mut stream = TcpStream::connect(server_addr)?;
-    stream.write_all(&[b'U'])?;
+    let mut stream = server_addr.connect()?;
+    stream.write_all(if mode.is_some() { b"A" } else { b"U" })?;
 
     stream.write_u16::<BigEndian>(file_name.len() as u16)?;
     stream.write_all(&file_name)?;
     stream.write_u64::<BigEndian>(total_size)?;
+    if let Some(mode) = mode {
+        stream.write_u32::<BigEndian>(mode)?;
+    }
 
     let mut sent_bytes: u64 = 0;
     let mut buffer = [0u8; 8192];
     let start_time = Instant::now();
     let mut last_time = Instant::now();
-    let mut last_sent: u64 = 0;
+    let mut window = SpeedWindow::new(speed::speed_window(), start_time, 0);
+    let mut crc = checksum::Crc32::new();
 
     loop {
         let n = file.read(&mut buffer)?;
         if n == 0 {
             break;
         }
-        stream.write_all(&buffer[..n])?;
+        if let Err(e) = stream.write_all(&buffer[..n]) {
+            return Err(busy_reply_or(&mut stream, e));
+        }
+        crc.update(&buffer[..n]);
         sent_bytes += n as u64;
 
         let now = Instant::now();
+        window.record(now, sent_bytes);
         let elapsed_since_last = now.duration_since(last_time);
 
         if elapsed_since_last >= Duration::from_millis(200) {
             let progress = (sent_bytes as f64 / total_size as f64) * 100.0;
             let total_elapsed = now.duration_since(start_time).as_secs_f64();
 
-            let delta_bytes = sent_bytes - last_sent;
-            let instant_speed = (delta_bytes as f64 / (1024.0 * 1024.0)) / elapsed_since_last.as_secs_f64();
+            let instant_speed = window.speed(now, sent_bytes);
 
             let avg_speed = (sent_bytes as f64 / (1024.0 * 1024.0)) / total_elapsed;
 
             on_progress(progress, instant_speed, avg_speed);
 
-            last_sent = sent_bytes;
             last_time = now;
         }
     }
@@ -96,101 +485,668 @@ where
     on_progress(100.0, 0.0, avg_speed);
 
     if sent_bytes != total_size {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        return Err(std::io::Error::other(
             format!("Upload incomplete: sent {} bytes, expected {} bytes", sent_bytes, total_size)
         ));
     }
 
-    let mut resp = String::new();
-    stream.read_to_string(&mut resp)?;
+    let resp = read_response_line(&mut stream)?;
     
+    if resp.trim() == "BUSY" {
+        return Err(server_busy());
+    }
     if !resp.trim().starts_with("OK") {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        return Err(std::io::Error::other(
             format!("Server rejected file: {}", resp.trim())
         ));
     }
 
-    Ok(())
+    verify_upload_ack(resp.trim(), sent_bytes, crc.finish())
+}
+
+/// Where a download into `destination` is received: `<name>.partial` next to
+/// it. The file is renamed to `destination` only once it is complete, so
+/// `destination` is either absent or a whole, verified copy.
+pub fn partial_download_path(destination: &Path) -> PathBuf {
+    let mut name = destination.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    destination.with_file_name(name)
 }
 
-pub fn download_file<F>(file_name: &str, destination: &Path, server_addr: &str, mut on_progress: F) -> std::io::Result<()> 
+/// A busy server is retried as [`BusyRetry::default`] says.
+pub fn download_file<F>(file_name: &str, destination: &Path, server_addr: &(impl Connect + ?Sized), on_progress: F) -> std::io::Result<()> 
 where
     F: FnMut(f64, f64, f64, f64),
 {
-    let mut stream = TcpStream::connect(server_addr)?;
-    stream.write_all(&[b'D'])?;
+    download_file_with_retry(file_name, destination, server_addr, &BusyRetry::default(), print_busy_retry, on_progress)
+}
+
+/// Same as [`download_file`] with an explicit busy policy; `on_busy` gets the
+/// delay before every retry.
+pub fn download_file_with_retry<F, B>(
+    file_name: &str,
+    destination: &Path,
+    server_addr: &(impl Connect + ?Sized),
+    retry: &BusyRetry,
+    on_busy: B,
+    mut on_progress: F,
+) -> std::io::Result<()>
+where
+    F: FnMut(f64, f64, f64, f64),
+    B: FnMut(Duration),
+{
+    busy_retry::retry_on_busy(retry, on_busy, || {
+        download_from_start(file_name.as_bytes(), destination, server_addr, Expected::default(), &mut on_progress)
+    })
+}
+
+/// Same as [`download_file_with_retry`], but gives up before writing anything
+/// if the server's copy is no longer `expected_size` bytes (as listed in
+/// [`RemoteFileInfo::size_bytes`]), so a download doesn't race a re-upload of
+/// the same name. Check for that with [`is_file_changed`].
+pub fn download_file_expecting_size<F, B>(
+    file_name: &str,
+    destination: &Path,
+    server_addr: &(impl Connect + ?Sized),
+    expected_size: u64,
+    retry: &BusyRetry,
+    on_busy: B,
+    mut on_progress: F,
+) -> std::io::Result<()>
+where
+    F: FnMut(f64, f64, f64, f64),
+    B: FnMut(Duration),
+{
+    let expected = Expected { size: Some(expected_size), ..Expected::default() };
+    busy_retry::retry_on_busy(retry, on_busy, || {
+        download_from_start(file_name.as_bytes(), destination, server_addr, expected, &mut on_progress)
+    })
+}
+
+/// Same as [`download_file_with_retry`] for a name that isn't valid UTF-8, given
+/// as the exact bytes from [`RemoteFileInfo::raw_name`]. With `expected_size`
+/// a file that changed since it was listed is refused as in
+/// [`download_file_expecting_size`].
+pub fn download_file_by_raw_name<F, B>(
+    raw_name: &[u8],
+    destination: &Path,
+    server_addr: &(impl Connect + ?Sized),
+    expected_size: Option<u64>,
+    retry: &BusyRetry,
+    on_busy: B,
+    mut on_progress: F,
+) -> std::io::Result<()>
+where
+    F: FnMut(f64, f64, f64, f64),
+    B: FnMut(Duration),
+{
+    let expected = Expected { size: expected_size, ..Expected::default() };
+    busy_retry::retry_on_busy(retry, on_busy, || {
+        download_from_start(raw_name, destination, server_addr, expected, &mut on_progress)
+    })
+}
+
+/// Same as [`download_file`], but the received bytes must also have CRC-32
+/// `expected_crc` (as listed by [`integrity_report`]) before the file appears
+/// at `destination`. A mismatching copy is deleted.
+pub fn download_file_verified<F>(
+    file_name: &str,
+    destination: &Path,
+    server_addr: &(impl Connect + ?Sized),
+    expected_crc: u32,
+    on_progress: F,
+) -> std::io::Result<()>
+where
+    F: FnMut(f64, f64, f64, f64),
+{
+    let expected = Expected { crc: Some(expected_crc), ..Expected::default() };
+    download_from_start(file_name.as_bytes(), destination, server_addr, expected, on_progress)
+}
+
+fn download_from_start<F>(
+    name_bytes: &[u8],
+    destination: &Path,
+    server_addr: &(impl Connect + ?Sized),
+    expected: Expected,
+    on_progress: F,
+) -> std::io::Result<()>
+where
+    F: FnMut(f64, f64, f64, f64),
+{
+    let mut stream = server_addr.connect()?;
+    stream.write_all(b"D")?;
     
-    let name_bytes = file_name.as_bytes();
     stream.write_u16::<BigEndian>(name_bytes.len() as u16)?;
     stream.write_all(name_bytes)?;
 
     let status = stream.read_u8()?;
+    receive_download(&mut stream, status, name_bytes, destination, 0, expected, on_progress)
+}
+
+/// Continues a download from however many bytes its `.partial` file (see
+/// [`partial_download_path`]) already holds. On failure the partial file is
+/// kept so the next call can pick up from there.
+pub fn resume_download<F>(file_name: &str, destination: &Path, server_addr: &(impl Connect + ?Sized), on_progress: F) -> std::io::Result<()>
+where
+    F: FnMut(f64, f64, f64, f64),
+{
+    resume_download_with_verification(file_name, destination, server_addr, false, on_progress)
+}
+
+/// Same as [`resume_download`]; with `verify_prefix` the bytes already on disk
+/// are checksummed first and the server restarts the download from zero if they
+/// don't match its copy. That costs a full read of the partial file on both ends.
+pub fn resume_download_with_verification<F>(
+    file_name: &str,
+    destination: &Path,
+    server_addr: &(impl Connect + ?Sized),
+    verify_prefix: bool,
+    on_progress: F,
+) -> std::io::Result<()>
+where
+    F: FnMut(f64, f64, f64, f64),
+{
+    resume_once(file_name, destination, server_addr, verify_prefix, Expected::default(), on_progress)
+}
+
+/// Bytes already received into the `.partial` file of `destination`, which a
+/// resumed download continues after.
+pub fn partial_download_size(destination: &Path) -> std::io::Result<u64> {
+    match std::fs::metadata(partial_download_path(destination)) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+/// Resumes like [`resume_download_with_verification`] with the prefix always
+/// verified, retrying a busy server as `retry` says. With `expected_size` the
+/// download is refused as in [`download_file_expecting_size`].
+pub fn resume_download_with_retry<F, B>(
+    file_name: &str,
+    destination: &Path,
+    server_addr: &(impl Connect + ?Sized),
+    expected_size: Option<u64>,
+    retry: &BusyRetry,
+    on_busy: B,
+    mut on_progress: F,
+) -> std::io::Result<()>
+where
+    F: FnMut(f64, f64, f64, f64),
+    B: FnMut(Duration),
+{
+    let expected = Expected { size: expected_size, ..Expected::default() };
+    busy_retry::retry_on_busy(retry, on_busy, || {
+        resume_once(file_name, destination, server_addr, true, expected, &mut on_progress)
+    })
+}
+
+fn resume_once<F>(
+    file_name: &str,
+    destination: &Path,
+    server_addr: &(impl Connect + ?Sized),
+    verify_prefix: bool,
+    expected: Expected,
+    on_progress: F,
+) -> std::io::Result<()>
+where
+    F: FnMut(f64, f64, f64, f64),
+{
+    let offset = partial_download_size(destination)?;
+
+    let mut stream = server_addr.connect()?;
+    let name_bytes = file_name.as_bytes();
+    if verify_prefix && offset > 0 {
+        let prefix_crc = checksum::crc32_prefix(File::open(partial_download_path(destination))?, offset)?;
+        stream.write_all(b"P")?;
+        stream.write_u16::<BigEndian>(name_bytes.len() as u16)?;
+        stream.write_all(name_bytes)?;
+        stream.write_u64::<BigEndian>(offset)?;
+        stream.write_u32::<BigEndian>(prefix_crc)?;
+    } else {
+        stream.write_all(b"R")?;
+        stream.write_u16::<BigEndian>(name_bytes.len() as u16)?;
+        stream.write_all(name_bytes)?;
+        stream.write_u64::<BigEndian>(offset)?;
+    }
+
+    let status = stream.read_u8()?;
+    if status == STATUS_PREFIX_MISMATCH {
+        println!("Partial '{}' does not match the server copy, restarting download", file_name);
+        stream.write_all(b"D")?;
+        stream.write_u16::<BigEndian>(name_bytes.len() as u16)?;
+        stream.write_all(name_bytes)?;
+        let status = stream.read_u8()?;
+        return receive_download(&mut stream, status, name_bytes, destination, 0, expected, on_progress);
+    }
+
+    receive_download(&mut stream, status, name_bytes, destination, offset, expected, on_progress)
+}
+
+fn receive_download<F>(
+    stream: &mut impl Transport,
+    status: u8,
+    name_bytes: &[u8],
+    destination: &Path,
+    offset: u64,
+    expected: Expected,
+    mut on_progress: F,
+) -> std::io::Result<()>
+where
+    F: FnMut(f64, f64, f64, f64),
+{
+    if status == SERVER_BUSY {
+        return Err(server_busy());
+    }
     if status == 0 {
         let msg_len = stream.read_u16::<BigEndian>()? as usize;
         let mut buf = vec![0u8; msg_len];
         stream.read_exact(&mut buf)?;
         let message = String::from_utf8(buf).unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, message));
+        return Err(std::io::Error::other(message));
     }
 
+    let file_name = String::from_utf8_lossy(name_bytes);
     let total_size = stream.read_u64::<BigEndian>()?;
-    let mut file = File::create(destination)?;
+    if let Some(listed) = expected.size
+        && listed != total_size
+    {
+        println!("'{}' changed on the server: listed with {} bytes, now {} bytes", file_name, listed, total_size);
+        return Err(std::io::Error::other(FileChanged { listed, actual: total_size }));
+    }
+    let partial = partial_download_path(destination);
+    let mut file = if offset > 0 {
+        let mut file = OpenOptions::new().write(true).open(&partial)?;
+        file.set_len(offset)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file
+    } else {
+        File::create(&partial)?
+    };
+    // Only paid for when there is something to check against.
+    let mut crc = match expected.crc {
+        Some(_) if offset > 0 => Some(checksum::Crc32::resume(checksum::crc32_prefix(File::open(&partial)?, offset)?)),
+        Some(_) => Some(checksum::Crc32::new()),
+        None => None,
+    };
 
-    let mut received: u64 = 0;
+    let mut received: u64 = offset;
     let mut buffer = [0u8; 8192];
     let start_time = Instant::now();
     let mut last_time = Instant::now();
-    let mut last_received: u64 = 0;
+    let mut window = SpeedWindow::new(speed::speed_window(), start_time, offset);
 
-    loop {
+    while received < total_size {
         let n = stream.read(&mut buffer)?;
         if n == 0 {
             break;
         }
         file.write_all(&buffer[..n])?;
+        if let Some(crc) = &mut crc {
+            crc.update(&buffer[..n]);
+        }
         received += n as u64;
 
         let now = Instant::now();
+        window.record(now, received);
         let elapsed_since_last = now.duration_since(last_time);
 
         if elapsed_since_last.as_millis() > 150 {
             let progress = (received as f64 / total_size as f64) * 100.0;
             let total_elapsed = now.duration_since(start_time).as_secs_f64().max(1e-6);
 
-            let delta = received - last_received;
-            let instant = (delta as f64 / (1024.0 * 1024.0)) / elapsed_since_last.as_secs_f64();
-            let avg = (received as f64 / (1024.0 * 1024.0)) / total_elapsed;
+            let instant = window.speed(now, received);
+            let avg = ((received - offset) as f64 / (1024.0 * 1024.0)) / total_elapsed;
             let time_now = start_time.elapsed().as_secs_f64().max(1e-6);
             on_progress(progress, instant, avg, time_now);
 
-            last_received = received;
             last_time = now;
         }
-
-        if received >= total_size {
-            break;
-        }
     }
 
     let total_elapsed = start_time.elapsed().as_secs_f64().max(1e-6);
-    let avg_speed = (received as f64 / (1024.0 * 1024.0)) / total_elapsed;
+    let avg_speed = ((received - offset) as f64 / (1024.0 * 1024.0)) / total_elapsed;
     let end_time = start_time.elapsed().as_secs_f64().max(1e-6);
     on_progress(100.0, 0.0, avg_speed, end_time);
 
     if received != total_size {
         println!("ERROR: File size mismatch for '{}': expected {} bytes, got {} bytes", file_name, total_size, received);
-        drop(file);
-        match std::fs::remove_file(&destination) {
-            Ok(_) => println!("Corrupted file '{}' has been deleted", file_name),
-            Err(e) => println!("Failed to delete corrupted file '{}': {}", file_name, e),
-        }
+        println!("Kept {:?} so the download can be resumed", partial);
         return Err(std::io::Error::new(
             std::io::ErrorKind::UnexpectedEof,
             format!("Download incomplete: received {} bytes, expected {} bytes", received, total_size)
         ));
     }
 
+    if let (Some(expected), Some(crc)) = (expected.crc, crc) {
+        let actual = crc.finish();
+        if actual != expected {
+            println!("ERROR: Checksum mismatch for '{}': expected {:08x}, got {:08x}", file_name, expected, actual);
+            drop(file);
+            match std::fs::remove_file(&partial) {
+                Ok(_) => println!("Corrupted file '{}' has been deleted", file_name),
+                Err(e) => println!("Failed to delete corrupted file '{}': {}", file_name, e),
+            }
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Download corrupted: checksum {:08x}, expected {:08x}", actual, expected)
+            ));
+        }
+    }
+
+    // On disk before the rename, so a crash can't leave a short file under the
+    // final name.
+    file.sync_all()?;
+    drop(file);
+    restore_remote_mode(stream, name_bytes, &partial);
+    std::fs::rename(&partial, destination)?;
+    Ok(())
+}
+
+/// Best effort: asks the server for the file's mode bits on the same connection
+/// and applies them. Servers without `S` close the connection, which is ignored.
+fn restore_remote_mode(stream: &mut impl Transport, name_bytes: &[u8], destination: &Path) {
+    if !cfg!(unix) {
+        return;
+    }
+
+    let mut query_mode = || -> std::io::Result<Option<u32>> {
+        stream.write_all(b"S")?;
+        stream.write_u16::<BigEndian>(name_bytes.len() as u16)?;
+        stream.write_all(name_bytes)?;
+        if stream.read_u8()? != 1 {
+            return Ok(None);
+        }
+        Ok(Some(stream.read_u32::<BigEndian>()?))
+    };
+
+    if let Ok(Some(mode)) = query_mode()
+        && let Err(e) = permissions::apply_mode(destination, mode)
+    {
+        println!("Failed to restore mode {:o} on {:?}: {}", mode, destination, e);
+    }
+}
+
+pub fn download_head(file_name: &str, n_bytes: u64, server_addr: &(impl Connect + ?Sized)) -> std::io::Result<Vec<u8>> {
+    let mut stream = server_addr.connect()?;
+    stream.write_all(b"H")?;
+
+    let name_bytes = file_name.as_bytes();
+    stream.write_u16::<BigEndian>(name_bytes.len() as u16)?;
+    stream.write_all(name_bytes)?;
+    stream.write_u64::<BigEndian>(n_bytes)?;
+
+    let status = stream.read_u8()?;
+    if status == SERVER_BUSY {
+        return Err(server_busy());
+    }
+    if status == 0 {
+        let msg_len = stream.read_u16::<BigEndian>()? as usize;
+        let mut buf = vec![0u8; msg_len];
+        stream.read_exact(&mut buf)?;
+        let message = String::from_utf8(buf).unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(std::io::Error::other(message));
+    }
+
+    let length = stream.read_u64::<BigEndian>()?;
+    if length > n_bytes {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Server sent {} bytes, asked for at most {}", length, n_bytes)
+        ));
+    }
+
+    let mut data = vec![0u8; length as usize];
+    stream.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Moves `source` to `destination` on the server, both relative to its uploads
+/// directory (e.g. `"report.pdf"` to `"archive/2024/report.pdf"`); missing
+/// directories are created. Fails if `destination` already exists.
+pub fn move_file(source: &str, destination: &str, server_addr: &(impl Connect + ?Sized)) -> std::io::Result<()> {
+    send_move(source, destination, server_addr, false)
+}
+
+/// Same as [`move_file`], replacing whatever is stored at `destination`.
+pub fn move_file_overwriting(source: &str, destination: &str, server_addr: &(impl Connect + ?Sized)) -> std::io::Result<()> {
+    send_move(source, destination, server_addr, true)
+}
+
+fn send_move(source: &str, destination: &str, server_addr: &(impl Connect + ?Sized), overwrite: bool) -> std::io::Result<()> {
+    let mut stream = server_addr.connect()?;
+    stream.write_all(b"M")?;
+    stream.write_u8(if overwrite { MOVE_FLAG_OVERWRITE } else { 0 })?;
+    for name in [source, destination] {
+        let name_bytes = name.as_bytes();
+        stream.write_u16::<BigEndian>(name_bytes.len() as u16)?;
+        stream.write_all(name_bytes)?;
+    }
+
+    let status = stream.read_u8()?;
+    if status == SERVER_BUSY {
+        return Err(server_busy());
+    }
+    if status == 0 {
+        let msg_len = stream.read_u16::<BigEndian>()? as usize;
+        let mut buf = vec![0u8; msg_len];
+        stream.read_exact(&mut buf)?;
+        let message = String::from_utf8(buf).unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(std::io::Error::other(message));
+    }
     Ok(())
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+/// One transfer in flight on the server, as reported by [`fetch_transfers`].
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferStatus {
+    pub direction: TransferDirection,
+    pub file_name: String,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub elapsed: Duration,
+}
+
+/// Lists the uploads and downloads the server is running for all its clients,
+/// oldest first.
+pub fn fetch_transfers(server_addr: &(impl Connect + ?Sized)) -> std::io::Result<Vec<TransferStatus>> {
+    let mut stream = server_addr.connect()?;
+    stream.write_all(b"X")?;
+
+    // A real count never reaches 0x4200, since transfers are bounded by the
+    // server's connection limit, so a leading `SERVER_BUSY` byte is a rejection.
+    let count = match stream.read_u8()? {
+        SERVER_BUSY => return Err(server_busy()),
+        high => u16::from_be_bytes([high, stream.read_u8()?]) as usize,
+    };
+    let mut transfers = Vec::with_capacity(count);
+    for _ in 0..count {
+        let direction = match stream.read_u8()? {
+            b'U' => TransferDirection::Upload,
+            b'D' => TransferDirection::Download,
+            other => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Unknown transfer direction {}", other)
+                ));
+            }
+        };
+        let (file_name, bytes_done) = read_listing_entry(&mut stream)?;
+        let bytes_total = stream.read_u64::<BigEndian>()?;
+        let elapsed = Duration::from_millis(stream.read_u64::<BigEndian>()?);
+        transfers.push(TransferStatus { direction, file_name, bytes_done, bytes_total, elapsed });
+    }
+    Ok(transfers)
+}
+
+/// One stored file as listed by [`integrity_report`].
+#[derive(Debug, Clone, Serialize)]
+pub struct FileIntegrity {
+    pub name: String,
+    pub size: u64,
+    pub modified: std::time::SystemTime,
+    /// CRC-32 of the whole file, the same checksum upload acks carry.
+    pub crc32: u32,
+}
+
+/// Name, size, modification time and checksum of every file the server stores.
+pub fn integrity_report(server_addr: &(impl Connect + ?Sized)) -> std::io::Result<Vec<FileIntegrity>> {
+    let mut files = Vec::new();
+    stream_integrity_report(server_addr, |file| files.push(file))?;
+    Ok(files)
+}
+
+/// Like [`integrity_report`], handing each file to `on_file` as soon as the
+/// server has checksummed it.
+pub fn stream_integrity_report<F>(server_addr: &(impl Connect + ?Sized), mut on_file: F) -> std::io::Result<()>
+where
+    F: FnMut(FileIntegrity),
+{
+    let mut stream = server_addr.connect()?;
+    stream.write_all(b"I")?;
+
+    loop {
+        match stream.read_u8()? {
+            LIST_FRAME_END => return Ok(()),
+            LIST_FRAME_ENTRY => {
+                let (name, size) = read_listing_entry(&mut stream)?;
+                let modified = std::time::UNIX_EPOCH + Duration::from_secs(stream.read_u64::<BigEndian>()?);
+                let crc32 = stream.read_u32::<BigEndian>()?;
+                on_file(FileIntegrity { name, size, modified, crc32 });
+            }
+            SERVER_BUSY => return Err(server_busy()),
+            other => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Unexpected integrity report frame {}", other)
+                ));
+            }
+        }
+    }
+}
+
+/// Round-trip time of one tiny request to the server, measured once the
+/// connection is up so the TCP handshake isn't included. Servers without the
+/// ping command close the connection, which is reported as `Unsupported`.
+pub fn ping(server_addr: &(impl Connect + ?Sized)) -> std::io::Result<Duration> {
+    // The top byte stays zero so the reply can't be mistaken for `SERVER_BUSY`.
+    let nonce = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64)
+        & 0x00ff_ffff_ffff_ffff;
+
+    let mut stream = server_addr.connect()?;
+    let started = Instant::now();
+    stream.write_all(b"E")?;
+    stream.write_u64::<BigEndian>(nonce)?;
+    let echoed = match stream.read_u8() {
+        Ok(SERVER_BUSY) => return Err(server_busy()),
+        Ok(first) => {
+            let mut rest = [0u8; 7];
+            stream.read_exact(&mut rest)?;
+            let mut bytes = [first; 8];
+            bytes[1..].copy_from_slice(&rest);
+            u64::from_be_bytes(bytes)
+        }
+        Err(e) if is_unsupported_command(&e) => {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Server does not support ping"));
+        }
+        Err(e) => return Err(e),
+    };
+    let elapsed = started.elapsed();
+
+    if echoed != nonce {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Ping reply does not match the request"));
+    }
+    Ok(elapsed)
+}
+
+/// What a server supports, from [`fetch_capabilities`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ServerCapabilities {
+    /// Highest typed listing version it answers `V` with; 0 if it predates the
+    /// capabilities query.
+    pub max_listing_version: u8,
+    pub features: u32,
+}
+
+impl ServerCapabilities {
+    /// A server that doesn't know the capabilities query: plain uploads,
+    /// downloads and listings only.
+    pub const BASELINE: ServerCapabilities = ServerCapabilities { max_listing_version: 0, features: 0 };
+
+    fn has(&self, feature: u32) -> bool {
+        self.features & feature != 0
+    }
+
+    pub fn supports_file_modes(&self) -> bool {
+        self.has(CAP_FILE_MODES)
+    }
+
+    pub fn supports_resume(&self) -> bool {
+        self.has(CAP_RESUME)
+    }
+
+    pub fn supports_verified_resume(&self) -> bool {
+        self.has(CAP_VERIFIED_RESUME)
+    }
+
+    /// Upload acks carry the CRC-32 of the received bytes.
+    pub fn acks_with_checksum(&self) -> bool {
+        self.has(CAP_UPLOAD_ACK_CHECKSUM)
+    }
+
+    pub fn supports_head(&self) -> bool {
+        self.has(CAP_HEAD)
+    }
+
+    pub fn supports_move(&self) -> bool {
+        self.has(CAP_MOVE)
+    }
+
+    pub fn supports_transfers(&self) -> bool {
+        self.has(CAP_TRANSFERS)
+    }
+
+    pub fn supports_integrity_report(&self) -> bool {
+        self.has(CAP_INTEGRITY_REPORT)
+    }
+
+    /// Identical uploads are stored once on the server.
+    pub fn deduplicates(&self) -> bool {
+        self.has(CAP_DEDUP)
+    }
+
+    /// Uploads and moves are refused; only listing and downloads work.
+    pub fn is_read_only(&self) -> bool {
+        self.has(CAP_READ_ONLY)
+    }
+
+    pub fn supports_ping(&self) -> bool {
+        self.has(CAP_PING)
+    }
+}
+
+/// Asks the server which optional commands and behaviours it has, so callers can
+/// pick features once instead of trying them. A server older than the query
+/// reports [`ServerCapabilities::BASELINE`].
+pub fn fetch_capabilities(server_addr: &(impl Connect + ?Sized)) -> std::io::Result<ServerCapabilities> {
+    let mut stream = server_addr.connect()?;
+    stream.write_all(b"C")?;
+
+    let max_listing_version = match stream.read_u8() {
+        Ok(SERVER_BUSY) => return Err(server_busy()),
+        Ok(version) => version,
+        Err(e) if is_unsupported_command(&e) => return Ok(ServerCapabilities::BASELINE),
+        Err(e) => return Err(e),
+    };
+    let features = stream.read_u32::<BigEndian>()?;
+    Ok(ServerCapabilities { max_listing_version, features })
+}
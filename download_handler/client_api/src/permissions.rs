@@ -0,0 +1,31 @@
+use std::path::Path;
+
+/// Only the rwx bits travel with a file; setuid, setgid and sticky are always dropped.
+pub const TRANSFERABLE_MODE_MASK: u32 = 0o777;
+
+/// Mode bits of `path`, or 0 ("unknown") on platforms without Unix permissions.
+#[cfg(unix)]
+pub fn file_mode(path: &Path) -> std::io::Result<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(std::fs::metadata(path)?.permissions().mode() & TRANSFERABLE_MODE_MASK)
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(_path: &Path) -> std::io::Result<u32> {
+    Ok(0)
+}
+
+/// Applies `mode` to `path`; a mode of 0 means the sender didn't know it and is ignored.
+#[cfg(unix)]
+pub fn apply_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if mode == 0 {
+        return Ok(());
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & TRANSFERABLE_MODE_MASK))
+}
+
+#[cfg(not(unix))]
+pub fn apply_mode(_path: &Path, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Weight of the newest sample in [`SpeedSmoother`]; lower is steadier.
+pub const DEFAULT_SPEED_SMOOTHING: f64 = 0.3;
+
+/// Exponential moving average over the per-window "instant" speeds reported by
+/// the progress callbacks, so a UI readout doesn't flicker on bursty links.
+#[derive(Debug, Clone)]
+pub struct SpeedSmoother {
+    factor: f64,
+    value: Option<f64>,
+}
+
+impl Default for SpeedSmoother {
+    fn default() -> Self {
+        Self::new(DEFAULT_SPEED_SMOOTHING)
+    }
+}
+
+impl SpeedSmoother {
+    /// `factor` is clamped to `0.0..=1.0`; 1.0 turns smoothing off.
+    pub fn new(factor: f64) -> Self {
+        Self {
+            factor: factor.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    /// Feeds one instant-speed sample and returns the smoothed speed.
+    pub fn update(&mut self, sample: f64) -> f64 {
+        let smoothed = match self.value {
+            Some(previous) => previous + self.factor * (sample - previous),
+            None => sample,
+        };
+        self.value = Some(smoothed);
+        smoothed
+    }
+}
+
+/// Span the "instant" transfer speed is measured over, independent of how often
+/// progress is reported.
+pub const DEFAULT_SPEED_WINDOW: Duration = Duration::from_millis(500);
+/// How many samples a [`SpeedWindow`] keeps per window at most.
+const SPEED_WINDOW_SAMPLES: u32 = 32;
+
+static SPEED_WINDOW_MS: AtomicU64 = AtomicU64::new(DEFAULT_SPEED_WINDOW.as_millis() as u64);
+
+/// Sets the window used for the instant speed of every transfer started
+/// afterwards. Longer is steadier on high-latency links, shorter reacts faster.
+pub fn set_speed_window(window: Duration) {
+    SPEED_WINDOW_MS.store(window.as_millis().max(1) as u64, Ordering::Relaxed);
+}
+
+pub fn speed_window() -> Duration {
+    Duration::from_millis(SPEED_WINDOW_MS.load(Ordering::Relaxed))
+}
+
+/// Transfer speed over the last `window`, from a small ring of timestamped
+/// byte counts.
+#[derive(Debug, Clone)]
+pub struct SpeedWindow {
+    window: Duration,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl SpeedWindow {
+    pub fn new(window: Duration, start: Instant, start_bytes: u64) -> Self {
+        let mut samples = VecDeque::with_capacity(SPEED_WINDOW_SAMPLES as usize + 2);
+        samples.push_back((start, start_bytes));
+        Self {
+            window: window.max(Duration::from_millis(1)),
+            samples,
+        }
+    }
+
+    /// Notes that `total_bytes` had been transferred by `now`.
+    pub fn record(&mut self, now: Instant, total_bytes: u64) {
+        let spacing = self.window / SPEED_WINDOW_SAMPLES;
+        if self.samples.back().is_none_or(|&(time, _)| now.duration_since(time) >= spacing) {
+            self.samples.push_back((now, total_bytes));
+        }
+        // Keep one sample from before the window as the baseline.
+        while self.samples.len() > 1 && now.duration_since(self.samples[1].0) >= self.window {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Speed in MB/s between the oldest sample in the window and (`now`, `total_bytes`).
+    pub fn speed(&self, now: Instant, total_bytes: u64) -> f64 {
+        let Some(&(since, since_bytes)) = self.samples.front() else {
+            return 0.0;
+        };
+        let seconds = now.duration_since(since).as_secs_f64();
+        if seconds <= 0.0 {
+            return 0.0;
+        }
+        (total_bytes.saturating_sub(since_bytes) as f64 / (1024.0 * 1024.0)) / seconds
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::{fetch_available_files, Connect, is_server_busy, upload_file_with_retry, BusyRetry};
+
+/// The server accepts at most 10 connections, so stay well below that by default.
+pub const DEFAULT_SYNC_PARALLELISM: usize = 4;
+pub const DEFAULT_SYNC_MAX_RETRIES: u32 = 5;
+pub const DEFAULT_SYNC_RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    pub parallelism: usize,
+    /// How many times a file is retried after the server turned it away for
+    /// being at its connection limit.
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled after every further attempt.
+    pub retry_backoff: Duration,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            parallelism: DEFAULT_SYNC_PARALLELISM,
+            max_retries: DEFAULT_SYNC_MAX_RETRIES,
+            retry_backoff: DEFAULT_SYNC_RETRY_BACKOFF,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncOutcome {
+    Uploaded { attempts: u32 },
+    /// The server already has a file with this name and size.
+    Skipped,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncFileResult {
+    pub path: PathBuf,
+    pub outcome: SyncOutcome,
+}
+
+/// Per-file results, in the same (sorted by file name) order the files were picked up.
+#[derive(Debug, Clone, Default)]
+pub struct SyncSummary {
+    pub results: Vec<SyncFileResult>,
+}
+
+impl SyncSummary {
+    pub fn uploaded(&self) -> usize {
+        self.count(|outcome| matches!(outcome, SyncOutcome::Uploaded { .. }))
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(|outcome| *outcome == SyncOutcome::Skipped)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|outcome| matches!(outcome, SyncOutcome::Failed(_)))
+    }
+
+    fn count(&self, matches: impl Fn(&SyncOutcome) -> bool) -> usize {
+        self.results.iter().filter(|result| matches(&result.outcome)).count()
+    }
+}
+
+/// Uploads every regular file directly inside `dir` that the server doesn't
+/// already have, using up to `options.parallelism` connections at a time.
+/// Files are handed out in file-name order and one failing file doesn't stop
+/// the rest; `on_result` is called as each file finishes.
+pub fn sync_directory<F>(dir: &Path, server_addr: &(impl Connect + Sync + ?Sized), options: &SyncOptions, on_result: F) -> std::io::Result<SyncSummary>
+where
+    F: Fn(&SyncFileResult) + Sync,
+{
+    let mut files: Vec<(PathBuf, u64)> = Vec::new();
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        if entry.file_type()?.is_file() {
+            files.push((entry.path(), entry.metadata()?.len()));
+        }
+    }
+    files.sort_by(|a, b| a.0.file_name().cmp(&b.0.file_name()));
+
+    let remote: HashMap<String, u64> = fetch_available_files(server_addr)?
+        .into_iter()
+        .map(|file| (file.name, file.size_bytes))
+        .collect();
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<SyncFileResult>>> = Mutex::new(vec![None; files.len()]);
+    let workers = options.parallelism.clamp(1, files.len().max(1));
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some((path, size)) = files.get(index) else {
+                    break;
+                };
+
+                let already_synced = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| remote.get(name))
+                    .is_some_and(|remote_size| remote_size == size);
+                let outcome = if already_synced {
+                    SyncOutcome::Skipped
+                } else {
+                    upload_with_retries(path, server_addr, options)
+                };
+
+                let result = SyncFileResult { path: path.clone(), outcome };
+                on_result(&result);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    let summary = SyncSummary {
+        results: results.into_inner().unwrap().into_iter().flatten().collect(),
+    };
+    println!(
+        "Sync of {:?} finished: {} uploaded, {} skipped, {} failed",
+        dir,
+        summary.uploaded(),
+        summary.skipped(),
+        summary.failed()
+    );
+    Ok(summary)
+}
+
+fn upload_with_retries(path: &Path, server_addr: &(impl Connect + ?Sized), options: &SyncOptions) -> SyncOutcome {
+    let mut backoff = options.retry_backoff;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        // Retries here also cover servers that drop the connection instead of
+        // answering BUSY, so the per-upload busy retry is off.
+        match upload_file_with_retry(path, server_addr, &BusyRetry::disabled(), |_| {}, |_, _, _| {}) {
+            Ok(_) => return SyncOutcome::Uploaded { attempts },
+            Err(e) if is_connection_dropped(&e) && attempts <= options.max_retries => {
+                println!("Upload of {:?} turned away by server ({}), retrying in {:?}", path, e, backoff);
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            Err(e) => return SyncOutcome::Failed(e.to_string()),
+        }
+    }
+}
+
+/// A server at its connection limit answers `BUSY`; older ones just close the socket.
+fn is_connection_dropped(e: &std::io::Error) -> bool {
+    is_server_busy(e) || matches!(
+        e.kind(),
+        std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::BrokenPipe
+            | std::io::ErrorKind::UnexpectedEof
+    )
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::fs::{create_dir_all, hard_link, remove_file, rename};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use client_api::{file_mode, TRANSFERABLE_MODE_MASK};
+use sha2::{Digest, Sha256};
+
+/// Blobs live here, named by the hex SHA-256 of their content. Being a dotfile
+/// it is never listed, served or counted towards the storage limit.
+const BLOB_DIR: &str = ".blobs";
+/// Stored name -> content hash, one `<hash>\t<name>` line per upload.
+const INDEX_FILE: &str = ".dedup-index";
+
+static DEDUP_ENABLED: AtomicBool = AtomicBool::new(false);
+static NEXT_STAGING_ID: AtomicU64 = AtomicU64::new(0);
+/// Held while blobs are linked or collected and the index is rewritten.
+static DEDUP_LOCK: Mutex<()> = Mutex::new(());
+
+/// Stores uploads once per distinct content. Each stored name is a hard link to
+/// its blob, so listing, downloads and moves see an ordinary file. Files with the
+/// same content share their mode until one of them is given another mode, which
+/// gives that file its own copy (see [`apply_mode`]).
+pub fn enable() {
+    DEDUP_ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    DEDUP_ENABLED.load(Ordering::Relaxed)
+}
+
+/// SHA-256 of an upload, fed as the bytes arrive.
+pub struct ContentHasher(Sha256);
+
+impl ContentHasher {
+    pub fn new() -> Self {
+        Self(Sha256::new())
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    pub fn finish(self) -> String {
+        self.0.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// Fresh path inside the blob directory to receive an upload into before its
+/// hash is known.
+pub fn staging_path(uploads_dir: &Path) -> std::io::Result<PathBuf> {
+    let blob_dir = uploads_dir.join(BLOB_DIR);
+    create_dir_all(&blob_dir)?;
+    let id = NEXT_STAGING_ID.fetch_add(1, Ordering::Relaxed);
+    Ok(blob_dir.join(format!("incoming-{}-{}", std::process::id(), id)))
+}
+
+/// Turns the fully received `staged` file into the blob for `hash` (or drops it
+/// if that blob already exists) and links `target`, stored as `name`, to it. The
+/// blob `name` used to point to is removed once no stored name refers to it.
+pub fn store(uploads_dir: &Path, staged: &Path, target: &Path, name: &str, hash: String) -> std::io::Result<()> {
+    let _guard = DEDUP_LOCK.lock().unwrap();
+    let blob = uploads_dir.join(BLOB_DIR).join(&hash);
+    if blob.exists() {
+        remove_file(staged)?;
+        println!("Upload '{}' has the same content as blob {}; stored once", name, hash);
+    } else {
+        rename(staged, &blob)?;
+    }
+
+    match remove_file(target) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    hard_link(&blob, target)?;
+
+    let mut index = load_index(uploads_dir)?;
+    if let Some(old_hash) = index.insert(name.to_string(), hash) {
+        remove_if_unreferenced(uploads_dir, &index, &old_hash);
+    }
+    save_index(uploads_dir, &index)
+}
+
+/// Applies `mode` to the stored file `target`. When other stored names share its
+/// blob, `target` first gets a copy of its own and leaves the index, so the
+/// others keep their mode.
+pub fn apply_mode(uploads_dir: &Path, target: &Path, mode: u32) -> std::io::Result<()> {
+    if mode == 0 || file_mode(target)? == mode & TRANSFERABLE_MODE_MASK {
+        return Ok(());
+    }
+
+    let _guard = DEDUP_LOCK.lock().unwrap();
+    // One link is the blob's own, one is `target`; any more are other names.
+    if link_count(target)? > 2 {
+        let copy = staging_path(uploads_dir)?;
+        std::fs::copy(target, &copy)?;
+        rename(&copy, target)?;
+        forget_locked(uploads_dir, target)?;
+    }
+    client_api::apply_mode(target, mode)
+}
+
+/// Drops the index entry of a stored file that was deleted (or no longer shares
+/// a blob), removing the blob if nothing else refers to it.
+pub fn forget(uploads_dir: &Path, path: &Path) -> std::io::Result<()> {
+    let _guard = DEDUP_LOCK.lock().unwrap();
+    forget_locked(uploads_dir, path)
+}
+
+fn forget_locked(uploads_dir: &Path, path: &Path) -> std::io::Result<()> {
+    let Some(name) = index_name(uploads_dir, path) else {
+        return Ok(());
+    };
+    let mut index = load_index(uploads_dir)?;
+    let Some(hash) = index.remove(&name) else {
+        return Ok(());
+    };
+    remove_if_unreferenced(uploads_dir, &index, &hash);
+    save_index(uploads_dir, &index)
+}
+
+/// Moves the index entry of a stored file renamed from `source` to
+/// `destination`. An entry the move overwrote is dropped like a deleted file.
+pub fn moved(uploads_dir: &Path, source: &Path, destination: &Path) -> std::io::Result<()> {
+    let _guard = DEDUP_LOCK.lock().unwrap();
+    let (Some(source_name), Some(destination_name)) =
+        (index_name(uploads_dir, source), index_name(uploads_dir, destination))
+    else {
+        return Ok(());
+    };
+    let mut index = load_index(uploads_dir)?;
+    let moved_hash = index.remove(&source_name);
+    let tracked = moved_hash.is_some();
+    let overwritten = match moved_hash {
+        Some(hash) => index.insert(destination_name, hash),
+        None => index.remove(&destination_name),
+    };
+    if !tracked && overwritten.is_none() {
+        return Ok(());
+    }
+    if let Some(old_hash) = overwritten {
+        remove_if_unreferenced(uploads_dir, &index, &old_hash);
+    }
+    save_index(uploads_dir, &index)
+}
+
+/// Only the blob's own link goes; stored names still linked to it keep the content.
+fn remove_if_unreferenced(uploads_dir: &Path, index: &HashMap<String, String>, hash: &str) {
+    if index.values().any(|other| other == hash) {
+        return;
+    }
+    match remove_file(uploads_dir.join(BLOB_DIR).join(hash)) {
+        Ok(()) => println!("Removed unreferenced blob {}", hash),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => println!("Failed to remove unreferenced blob {}: {}", hash, e),
+    }
+}
+
+/// Index key of a stored file: its path below the uploads root, `/` separated.
+fn index_name(uploads_dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(uploads_dir).ok()?;
+    let components: Option<Vec<&str>> = relative.iter().map(|component| component.to_str()).collect();
+    Some(components?.join("/"))
+}
+
+#[cfg(unix)]
+fn link_count(path: &Path) -> std::io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(std::fs::metadata(path)?.nlink())
+}
+
+/// Modes aren't applied on other platforms, so there is nothing to keep apart.
+#[cfg(not(unix))]
+fn link_count(_path: &Path) -> std::io::Result<u64> {
+    Ok(1)
+}
+
+fn load_index(uploads_dir: &Path) -> std::io::Result<HashMap<String, String>> {
+    let contents = match std::fs::read_to_string(uploads_dir.join(INDEX_FILE)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(hash, name)| (name.to_string(), hash.to_string()))
+        .collect())
+}
+
+/// Written next to the index and renamed over it, so a crash never leaves a
+/// truncated index behind.
+fn save_index(uploads_dir: &Path, index: &HashMap<String, String>) -> std::io::Result<()> {
+    let mut entries: Vec<(&String, &String)> = index.iter().collect();
+    entries.sort();
+    let contents: String = entries
+        .into_iter()
+        .map(|(name, hash)| format!("{}\t{}\n", hash, name))
+        .collect();
+    let staged = uploads_dir.join(format!("{}.tmp", INDEX_FILE));
+    std::fs::write(&staged, contents)?;
+    rename(staged, uploads_dir.join(INDEX_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store_content(uploads_dir: &Path, name: &str, content: &[u8]) {
+        let staged = staging_path(uploads_dir).unwrap();
+        std::fs::write(&staged, content).unwrap();
+        let mut hasher = ContentHasher::new();
+        hasher.update(content);
+        store(uploads_dir, &staged, &uploads_dir.join(name), name, hasher.finish()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn changing_one_mode_keeps_the_others_and_the_index_follows() {
+        let dir = std::env::temp_dir().join(format!("dedup-test-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        store_content(&dir, "a.txt", b"same");
+        store_content(&dir, "b.txt", b"same");
+        client_api::apply_mode(&dir.join("b.txt"), 0o644).unwrap();
+
+        apply_mode(&dir, &dir.join("a.txt"), 0o600).unwrap();
+        assert_eq!(file_mode(&dir.join("a.txt")).unwrap(), 0o600);
+        assert_eq!(file_mode(&dir.join("b.txt")).unwrap(), 0o644);
+        assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"same");
+        let index = load_index(&dir).unwrap();
+        assert_eq!(index.keys().collect::<Vec<_>>(), ["b.txt"]);
+
+        std::fs::rename(dir.join("b.txt"), dir.join("c.txt")).unwrap();
+        moved(&dir, &dir.join("b.txt"), &dir.join("c.txt")).unwrap();
+        let hash = load_index(&dir).unwrap().remove("c.txt").unwrap();
+        assert!(dir.join(BLOB_DIR).join(&hash).exists());
+
+        remove_file(dir.join("c.txt")).unwrap();
+        forget(&dir, &dir.join("c.txt")).unwrap();
+        assert!(load_index(&dir).unwrap().is_empty());
+        assert!(!dir.join(BLOB_DIR).join(&hash).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Instant, SystemTime};
+
+pub const DOWNLOAD_CACHE_MAX_BYTES: u64 = 256 * 1024 * 1024;
+pub const DOWNLOAD_CACHE_MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+/// Bound on the files remembered as downloaded once; the list starts over when
+/// it is full.
+const MAX_TRACKED_COLD_FILES: usize = 4096;
+
+/// On by default; `--no-download-cache` streams every download from disk.
+static DOWNLOAD_CACHE_ENABLED: AtomicBool = AtomicBool::new(true);
+
+type Slot = Arc<Mutex<Option<Arc<Vec<u8>>>>>;
+
+struct CacheEntry {
+    size: u64,
+    modified: SystemTime,
+    last_used: Instant,
+    slot: Slot,
+}
+
+static DOWNLOAD_CACHE: LazyLock<Mutex<HashMap<PathBuf, CacheEntry>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+/// Files downloaded once but not cached, with the size and modification time
+/// they had then. A file is only read into memory on its second download, so a
+/// client fetching many files once doesn't evict the ones asked for repeatedly.
+static REQUESTED_ONCE: LazyLock<Mutex<HashMap<PathBuf, (u64, SystemTime)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn disable() {
+    DOWNLOAD_CACHE_ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    DOWNLOAD_CACHE_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns the whole file from memory, reading it from disk only once even when
+/// several clients ask for it at the same time. `None` means the file should be
+/// streamed from disk directly (cache disabled, file too big or not asked for
+/// before).
+pub fn get_or_load(path: &Path, size: u64, modified: SystemTime) -> std::io::Result<Option<Arc<Vec<u8>>>> {
+    if !enabled() || size > DOWNLOAD_CACHE_MAX_FILE_SIZE {
+        return Ok(None);
+    }
+
+    let slot = {
+        let mut cache = DOWNLOAD_CACHE.lock().unwrap();
+        let stale = cache
+            .get(path)
+            .is_some_and(|entry| entry.size != size || entry.modified != modified);
+        if stale {
+            cache.remove(path);
+        }
+        if !cache.contains_key(path) && !requested_before(path, size, modified) {
+            return Ok(None);
+        }
+
+        let entry = cache.entry(path.to_path_buf()).or_insert_with(|| CacheEntry {
+            size,
+            modified,
+            last_used: Instant::now(),
+            slot: Arc::new(Mutex::new(None)),
+        });
+        entry.last_used = Instant::now();
+        Arc::clone(&entry.slot)
+    };
+
+    let mut data = slot.lock().unwrap();
+    if let Some(bytes) = data.as_ref() {
+        return Ok(Some(Arc::clone(bytes)));
+    }
+
+    let mut bytes = Vec::with_capacity(size as usize);
+    File::open(path)?.read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != size {
+        DOWNLOAD_CACHE.lock().unwrap().remove(path);
+        return Ok(None);
+    }
+
+    let bytes = Arc::new(bytes);
+    *data = Some(Arc::clone(&bytes));
+    drop(data);
+
+    evict_to_limit(path);
+    Ok(Some(bytes))
+}
+
+pub fn invalidate(path: &Path) {
+    DOWNLOAD_CACHE.lock().unwrap().remove(path);
+    REQUESTED_ONCE.lock().unwrap().remove(path);
+}
+
+/// Whether this version of the file was downloaded before; if not, remembers
+/// that it now has been.
+fn requested_before(path: &Path, size: u64, modified: SystemTime) -> bool {
+    let mut requested = REQUESTED_ONCE.lock().unwrap();
+    if requested.get(path) == Some(&(size, modified)) {
+        requested.remove(path);
+        return true;
+    }
+    if requested.len() >= MAX_TRACKED_COLD_FILES {
+        requested.clear();
+    }
+    requested.insert(path.to_path_buf(), (size, modified));
+    false
+}
+
+fn evict_to_limit(keep: &Path) {
+    let mut cache = DOWNLOAD_CACHE.lock().unwrap();
+    let mut total: u64 = cache.values().map(|entry| entry.size).sum();
+
+    while total > DOWNLOAD_CACHE_MAX_BYTES {
+        let oldest = cache
+            .iter()
+            .filter(|(path, _)| path.as_path() != keep)
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(path, _)| path.clone());
+
+        match oldest {
+            Some(path) => {
+                if let Some(entry) = cache.remove(&path) {
+                    total -= entry.size;
+                    println!("Download cache: evicted {:?} ({} bytes)", path, entry.size);
+                }
+            }
+            None => break,
+        }
+    }
+}
@@ -1,38 +1,315 @@
 use std::fs::{create_dir_all, read_dir, File};
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{Shutdown, TcpListener};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+use client_api::{content_type_for, crc32_prefix, file_mode, Crc32};
+
+mod dedup;
+mod download_cache;
+mod sanitize;
+mod shutdown;
+mod storage_limit;
+mod stored_name;
+mod transfers;
+mod transport;
+
+use stored_name::StoredName;
+use transport::Transport;
 
 const MAX_CONNECTIONS: usize = 10;
+/// Address served when no `--listen` is given.
+const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:4000";
+const UPLOADS_DIR: &str = "uploads";
+const TYPED_LIST_VERSION: u8 = 2;
+const LISTING_STATUS_VERSION: u8 = 3;
+const LIST_STATUS_CREATED: u8 = 0;
+const LIST_STATUS_EMPTY: u8 = 1;
+const LIST_STATUS_POPULATED: u8 = 2;
+const LIST_FRAME_END: u8 = 0;
+const LIST_FRAME_ENTRY: u8 = 1;
+/// Reply to a verified resume whose prefix checksum doesn't match; the client
+/// should start the download over.
+const STATUS_PREFIX_MISMATCH: u8 = 2;
+const MAX_HEAD_BYTES: u64 = 1024 * 1024;
+const COMMAND_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often a connection waiting for its next command checks for a shutdown.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long a shutdown waits for transfers in progress before exiting anyway.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+/// Timeout for a single socket read or write during a transfer; a timed-out
+/// operation is retried until `SLOW_CLIENT_GRACE` has passed without progress.
+const TRANSFER_IO_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a client may stall a transfer before it is dropped, so one slow
+/// peer can't keep a connection slot forever.
+const SLOW_CLIENT_GRACE: Duration = Duration::from_secs(30);
+/// Sent instead of serving a client while `MAX_CONNECTIONS` are busy. Its first
+/// byte never collides with a status, listing version or `OK`/`ERROR` reply.
+const BUSY_RESPONSE: &[u8] = b"BUSY\n";
+const BUSY_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+/// Numbers the `fs-worker-<n>` connection threads, so `top -H` and a debugger
+/// can tell them apart.
+static NEXT_WORKER_ID: AtomicU64 = AtomicU64::new(0);
+/// Stack of each connection thread. Transfer buffers are 8 KiB, so the default
+/// platform stack (usually 2 MiB reserved per thread) is mostly unused.
+const DEFAULT_WORKER_STACK_SIZE: usize = 256 * 1024;
+/// Smallest `--worker-stack-size` accepted. The deepest command (an upload with
+/// dedup hashing, in a debug build) fits with room to spare; a worker that
+/// overflows its stack aborts the whole server, not just its connection.
+const MIN_WORKER_STACK_SIZE: usize = 64 * 1024;
+static WORKER_STACK_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_WORKER_STACK_SIZE);
+/// Longest single file name accepted for uploads, in bytes (the usual limit on
+/// common filesystems).
+const DEFAULT_MAX_UPLOAD_NAME_BYTES: usize = 255;
+/// Longest full path an upload may end up at; Windows paths stop at 260 characters
+/// unless long path support is enabled.
+#[cfg(windows)]
+const DEFAULT_MAX_UPLOAD_PATH_LEN: usize = 259;
+#[cfg(not(windows))]
+const DEFAULT_MAX_UPLOAD_PATH_LEN: usize = 4095;
+/// Set by `--max-name-bytes` and `--max-path-len` for file systems with other limits.
+static MAX_UPLOAD_NAME_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_UPLOAD_NAME_BYTES);
+static MAX_UPLOAD_PATH_LEN: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_UPLOAD_PATH_LEN);
+/// Include the CRC-32 of the received bytes in the upload ack next to the size.
+const UPLOAD_ACK_CHECKSUM: bool = true;
+/// Set by `--show-hidden` to list and serve dotfiles and internal sidecar files
+/// as well (admin view).
+static SHOW_HIDDEN_FILES: AtomicBool = AtomicBool::new(false);
+const HIDDEN_SIDECAR_EXTENSIONS: [&str; 3] = ["sidecar", "partial", "meta"];
+/// Stream uncached downloads with sendfile(2) on Linux instead of copying them
+/// through a userspace buffer.
+const SENDFILE_ENABLED: bool = true;
+/// Largest single sendfile(2) call, so stalls are noticed between calls.
+#[cfg(target_os = "linux")]
+const SENDFILE_CHUNK: usize = 1024 * 1024;
+/// Flag bit of the move command allowing it to replace an existing destination.
+const MOVE_FLAG_OVERWRITE: u8 = 1;
+/// Feature bits of the `C` (capabilities) reply, which follow the highest
+/// listing version the server speaks.
+const CAP_FILE_MODES: u32 = 1 << 0;
+const CAP_RESUME: u32 = 1 << 1;
+const CAP_VERIFIED_RESUME: u32 = 1 << 2;
+const CAP_UPLOAD_ACK_CHECKSUM: u32 = 1 << 3;
+const CAP_HEAD: u32 = 1 << 4;
+const CAP_MOVE: u32 = 1 << 5;
+const CAP_TRANSFERS: u32 = 1 << 6;
+const CAP_INTEGRITY_REPORT: u32 = 1 << 7;
+/// Not a command: uploads with identical content are stored once.
+const CAP_DEDUP: u32 = 1 << 8;
+/// Not a command: uploads and moves are refused (`--read-only`).
+const CAP_READ_ONLY: u32 = 1 << 9;
+const CAP_PING: u32 = 1 << 10;
+/// Set by `--read-only` to serve the uploads directory without changing it.
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+const READ_ONLY_REASON: &str = "Operation not permitted: server is read-only";
+
+/// Serves one connection: a command byte and its arguments, repeated until the
+/// client disconnects, stays idle for `COMMAND_IDLE_TIMEOUT` or the server shuts
+/// down.
+fn handle_client(mut stream: impl Transport) -> std::io::Result<()> {
+    stream.set_write_timeout(Some(TRANSFER_IO_TIMEOUT))?;
+    loop {
+        let Some(command) = wait_for_command(&mut stream)? else {
+            return Ok(());
+        };
+        // A command's arguments (and an upload's body) may arrive slowly, but
+        // a client that stops sending mid-command must not hold its slot forever.
+        stream.set_read_timeout(Some(SLOW_CLIENT_GRACE))?;
+
+        if !handle_command(&mut stream, command)? {
+            return Ok(());
+        }
+    }
+}
 
-fn handle_client(mut stream: TcpStream) -> std::io::Result<()> {
-    let command = stream.read_u8()?;
+/// Waits up to `COMMAND_IDLE_TIMEOUT` for the next command byte, in short slices
+/// so idle connections close promptly on shutdown. `None` means the connection
+/// should be closed.
+fn wait_for_command(stream: &mut impl Transport) -> std::io::Result<Option<u8>> {
+    let idle_since = Instant::now();
+    stream.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))?;
+    loop {
+        if shutdown::requested() {
+            println!("Closing connection for shutdown");
+            return Ok(None);
+        }
+        match stream.read_u8() {
+            Ok(command) => return Ok(Some(command)),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) if is_timeout(&e) => {
+                if idle_since.elapsed() >= COMMAND_IDLE_TIMEOUT {
+                    println!("Closing idle connection");
+                    return Ok(None);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Runs one command; returns `false` when the connection should be closed.
+fn handle_command(stream: &mut impl Transport, command: u8) -> std::io::Result<bool> {
+    if matches!(command, b'U' | b'A' | b'M') && READ_ONLY.load(Ordering::Relaxed) {
+        return refuse_write(stream, command);
+    }
     match command {
-        b'U' => handle_upload(&mut stream),
-        b'D' => handle_download(&mut stream),
-        b'L' => handle_list(&mut stream),
+        b'U' => handle_upload(stream, false)?,
+        b'A' => handle_upload(stream, true)?,
+        b'S' => handle_stat_mode(stream)?,
+        b'D' => handle_download(stream)?,
+        b'R' => handle_resume_download(stream)?,
+        b'P' => handle_verified_resume(stream)?,
+        b'L' => handle_list(stream)?,
+        b'T' => handle_typed_list(stream)?,
+        b'V' => handle_versioned_list(stream)?,
+        b'H' => handle_head(stream)?,
+        b'M' => handle_move(stream)?,
+        b'X' => handle_transfers(stream)?,
+        b'I' => handle_integrity_report(stream)?,
+        b'C' => handle_capabilities(stream)?,
+        b'E' => handle_ping(stream)?,
         other => {
             println!("Unknown command: {other}");
-            Ok(())
+            return Ok(false);
         }
     }
+    Ok(true)
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)
+}
+
+fn stalled_client(what: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        format!("{} stalled for more than {:?}", what, SLOW_CLIENT_GRACE)
+    )
+}
+
+fn is_disconnect(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::BrokenPipe
+            | std::io::ErrorKind::UnexpectedEof
+    )
 }
 
 fn ensure_uploads_dir() -> std::io::Result<PathBuf> {
-    let uploads_dir = Path::new("uploads");
-    create_dir_all(uploads_dir)?;
-    uploads_dir.canonicalize()
+    ensure_uploads_dir_reporting_creation().map(|(path, _)| path)
+}
+
+/// Same as [`ensure_uploads_dir`], also telling whether the directory had to be created.
+fn ensure_uploads_dir_reporting_creation() -> std::io::Result<(PathBuf, bool)> {
+    let uploads_dir = uploads_dir();
+    let created = !uploads_dir.is_dir();
+    create_dir_all(&uploads_dir)?;
+    Ok((uploads_dir.canonicalize()?, created))
+}
+
+#[cfg(not(test))]
+fn uploads_dir() -> PathBuf {
+    PathBuf::from(UPLOADS_DIR)
+}
+
+/// Tests get a scratch directory, so they never leave files in (or collide with)
+/// the uploads of a server running from the crate directory.
+#[cfg(test)]
+fn uploads_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("server-test-uploads-{}", std::process::id()))
+}
+
+/// Dotfiles and the metadata files kept next to uploads (the dedup index and
+/// blobs among them) belong to the server: they are never moved, counted or
+/// evicted, whatever `--show-hidden` says.
+fn is_internal_file(name: &str) -> bool {
+    name.starts_with('.')
+        || Path::new(name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| HIDDEN_SIDECAR_EXTENSIONS.iter().any(|hidden| ext.eq_ignore_ascii_case(hidden)))
+}
+
+/// Internal files are not meant for users, so listings and downloads leave them
+/// out unless `--show-hidden` is set.
+fn is_hidden_file(name: &str) -> bool {
+    !SHOW_HIDDEN_FILES.load(Ordering::Relaxed) && is_internal_file(name)
+}
+
+/// Returns why an upload (or move) to `target_path` breaks `--max-name-bytes` or
+/// `--max-path-len`, if it does. Checked before anything is created, so an
+/// over-long name gets a clear reply instead of an obscure error from `File::create`.
+fn upload_path_too_long(file_name: &str, target_path: &Path) -> Option<String> {
+    let max_name_bytes = MAX_UPLOAD_NAME_BYTES.load(Ordering::Relaxed);
+    if file_name.len() > max_name_bytes {
+        return Some(format!(
+            "File name too long ({} bytes, limit {})",
+            file_name.len(),
+            max_name_bytes
+        ));
+    }
+
+    let max_path_len = MAX_UPLOAD_PATH_LEN.load(Ordering::Relaxed);
+    let path_len = target_path.as_os_str().len();
+    if path_len > max_path_len {
+        return Some(format!(
+            "File path too long ({} characters, limit {})",
+            path_len,
+            max_path_len
+        ));
+    }
+    None
+}
+
+/// On a case-insensitive file system (the default on macOS and Windows) an
+/// upload of `report.pdf` lands in an existing `Report.pdf`. Returns that
+/// existing name when `file_name` would resolve to a file listed under a
+/// different spelling, which the upload then renames to the spelling just
+/// uploaded. On case-sensitive roots the two are separate files and this is
+/// always `None`.
+fn case_only_collision(canonical_uploads: &Path, file_name: &str) -> std::io::Result<Option<String>> {
+    if std::fs::symlink_metadata(canonical_uploads.join(file_name)).is_err() {
+        return Ok(None);
+    }
+    let mut differently_cased = None;
+    for entry in read_dir(canonical_uploads)?.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name == file_name {
+            return Ok(None);
+        }
+        if name.to_lowercase() == file_name.to_lowercase() {
+            differently_cased = Some(name.into_owned());
+        }
+    }
+    Ok(differently_cased)
+}
+
+fn discard_upload_body(stream: &mut impl Transport, file_size: u64) -> std::io::Result<()> {
+    let discarded = std::io::copy(&mut stream.take(file_size), &mut std::io::sink())?;
+    if discarded != file_size {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Client disconnected"));
+    }
+    Ok(())
 }
 
-fn handle_upload(stream: &mut TcpStream) -> std::io::Result<()> {
+/// Receives a `U` upload, or an `A` one with the file's Unix mode bits sent
+/// right after the size. The file replaces a stored one of the same name. On a
+/// case-insensitive uploads directory that includes a name differing only in
+/// case: uploading `report.pdf` over `Report.pdf` replaces it, and the stored
+/// file is renamed to `report.pdf`.
+fn handle_upload(stream: &mut impl Transport, with_mode: bool) -> std::io::Result<()> {
     let name_len = stream.read_u16::<BigEndian>()? as usize;
     if name_len > 4096 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        return Err(std::io::Error::other(
             format!("You have very big name_file, len = {}", name_len)
         ));
     }
@@ -43,37 +320,129 @@ fn handle_upload(stream: &mut TcpStream) -> std::io::Result<()> {
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid UTF-8"))?;
 
     let file_size = stream.read_u64::<BigEndian>()?;
+    let mode = if with_mode { stream.read_u32::<BigEndian>()? } else { 0 };
 
-    let file_name = Path::new(&name_str)
-        .file_name()
-        .and_then(|s| s.to_str())
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Bad file name"))?;
+    let Some(sanitized_name) = sanitize::sanitize_file_name(&name_str) else {
+        println!("Rejected upload with unusable file name {:?}", name_str);
+        discard_upload_body(stream, file_size)?;
+        stream.write_all(b"ERROR Invalid file name\n")?;
+        return Ok(());
+    };
+    let file_name = sanitized_name.as_str();
+    // Uploading over the dedup index or a sidecar would replace server state.
+    if is_internal_file(file_name) {
+        println!("Rejected upload with internal file name {:?}", file_name);
+        discard_upload_body(stream, file_size)?;
+        stream.write_all(b"ERROR Invalid file name\n")?;
+        return Ok(());
+    }
 
     let canonical_uploads = ensure_uploads_dir()?;
     let target_path: PathBuf = canonical_uploads.join(file_name);
+
+    if let Some(reason) = upload_path_too_long(file_name, &target_path) {
+        println!("Rejected upload '{}': {}", file_name, reason);
+        discard_upload_body(stream, file_size)?;
+        stream.write_all(format!("ERROR {}\n", reason).as_bytes())?;
+        return Ok(());
+    }
     let canonical_target = target_path
         .canonicalize()
         .unwrap_or_else(|_| canonical_uploads.join(file_name));
 
     if !canonical_target.starts_with(&canonical_uploads) {
+        discard_upload_body(stream, file_size)?;
         stream.write_all(b"ERROR\n")?;
         return Ok(());
     }
 
-    let mut file = File::create(&canonical_target)?;
+    if let Some(reason) = storage_limit::admit(&canonical_uploads, &canonical_target)? {
+        println!("Rejected upload '{}': {}", file_name, reason);
+        discard_upload_body(stream, file_size)?;
+        stream.write_all(format!("ERROR {}\n", reason).as_bytes())?;
+        return Ok(());
+    }
+
+    // Replaced like any upload over an existing name, but stored under the
+    // spelling just uploaded so the listing matches what the client sent.
+    let replaced_name = case_only_collision(&canonical_uploads, file_name)?;
+    if let Some(existing) = &replaced_name {
+        download_cache::invalidate(&canonical_uploads.join(existing));
+    }
+    download_cache::invalidate(&canonical_target);
+    // With dedup the upload is received next to the blobs and only linked into
+    // place once its hash is known.
+    let receive_path = if dedup::enabled() {
+        dedup::staging_path(&canonical_uploads)?
+    } else {
+        canonical_target.clone()
+    };
+    let mut file = File::create(&receive_path)?;
     let mut remaining = file_size;
     let mut buffer = [0u8; 8192];
     let mut total_read = 0u64;
     let transfer_start = Instant::now();
+    let transfer = transfers::begin(transfers::Direction::Upload, file_name, 0, file_size);
+    let mut client_gone = false;
+    let mut stalled = false;
+    let mut last_progress = Instant::now();
+    let mut crc = Crc32::new();
+    let mut content_hash = dedup::enabled().then(dedup::ContentHasher::new);
+    stream.set_read_timeout(Some(TRANSFER_IO_TIMEOUT))?;
     while remaining > 0 {
         let to_read = std::cmp::min(buffer.len() as u64, remaining) as usize;
-        let n = stream.read(&mut buffer[..to_read])?;
-        if n == 0 {
-            break;
-        }
+        let n = match stream.read(&mut buffer[..to_read]) {
+            Ok(0) => {
+                client_gone = true;
+                break;
+            }
+            Ok(n) => {
+                last_progress = Instant::now();
+                n
+            }
+            Err(e) if is_timeout(&e) => {
+                if last_progress.elapsed() >= SLOW_CLIENT_GRACE {
+                    client_gone = true;
+                    stalled = true;
+                    break;
+                }
+                continue;
+            }
+            Err(e) if is_disconnect(&e) => {
+                client_gone = true;
+                break;
+            }
+            Err(e) => return Err(e),
+        };
         file.write_all(&buffer[..n])?;
+        if UPLOAD_ACK_CHECKSUM {
+            crc.update(&buffer[..n]);
+        }
+        if let Some(hasher) = content_hash.as_mut() {
+            hasher.update(&buffer[..n]);
+        }
         remaining -= n as u64;
         total_read += n as u64;
+        transfer.set_done(total_read);
+    }
+
+    if client_gone {
+        println!(
+            "Upload of '{}' incomplete: client {} after {} of {} bytes",
+            file_name,
+            if stalled { "stalled" } else { "disconnected" },
+            total_read,
+            file_size
+        );
+        drop(file);
+        match std::fs::remove_file(&receive_path) {
+            Ok(_) => println!("Partial file '{}' has been deleted", file_name),
+            Err(e) => println!("Failed to delete partial file '{}': {}", file_name, e),
+        }
+        if stalled {
+            return Err(stalled_client("Upload"));
+        }
+        return Ok(());
     }
 
     let actual_size = file.metadata()?.len();
@@ -84,13 +453,29 @@ fn handle_upload(stream: &mut TcpStream) -> std::io::Result<()> {
     if actual_size != file_size {
         println!("ERROR: File size mismatch for '{}': expected {} bytes, got {} bytes", file_name, file_size, actual_size);
         drop(file);
-        match std::fs::remove_file(&canonical_target) {
+        match std::fs::remove_file(&receive_path) {
             Ok(_) => println!("Corrupted file '{}' has been deleted", file_name),
             Err(e) => println!("Failed to delete corrupted file '{}': {}", file_name, e),
         }
         stream.write_all(b"ERROR\n")?;
         return Ok(());
     }
+
+    drop(file);
+    if let Some(hasher) = content_hash {
+        dedup::store(&canonical_uploads, &receive_path, &canonical_target, file_name, hasher.finish())?;
+    }
+    if let Some(existing) = &replaced_name {
+        println!("Upload '{}' replaced '{}' (names differ only in case)", file_name, existing);
+        std::fs::rename(canonical_uploads.join(existing), canonical_uploads.join(file_name))?;
+        dedup::forget(&canonical_uploads, &canonical_uploads.join(existing))?;
+    }
+    if let Err(e) = dedup::apply_mode(&canonical_uploads, &canonical_target, mode) {
+        println!("Failed to apply mode {:o} to '{}': {}", mode, file_name, e);
+    }
+    if let Err(e) = storage_limit::enforce(&canonical_uploads, &canonical_target) {
+        println!("Failed to enforce the stored file limit: {}", e);
+    }
     
     println!(
         "Received '{}' -> {:.2} MB in {:.3} s ({:.2} MB/s)",
@@ -99,129 +484,990 @@ fn handle_upload(stream: &mut TcpStream) -> std::io::Result<()> {
         elapsed,
         speed
     );
-    stream.write_all(b"OK\n")?;
+    let ack = if UPLOAD_ACK_CHECKSUM {
+        format!("OK {} {:08x}\n", actual_size, crc.finish())
+    } else {
+        format!("OK {}\n", actual_size)
+    };
+    stream.write_all(ack.as_bytes())?;
     Ok(())
 }
 
-fn handle_download(stream: &mut TcpStream) -> std::io::Result<()> {
+fn read_requested_name(stream: &mut impl Transport) -> std::io::Result<String> {
     let name_len = stream.read_u16::<BigEndian>()? as usize;
     let mut name_buf = vec![0u8; name_len];
     stream.read_exact(&mut name_buf)?;
-    let requested_name = String::from_utf8(name_buf)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid UTF-8"))?;
+    String::from_utf8(name_buf)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid UTF-8"))
+}
+
+/// Name of a stored file, which on Unix may be any bytes (see [`StoredName`]).
+fn read_stored_name(stream: &mut impl Transport) -> std::io::Result<StoredName> {
+    let name_len = stream.read_u16::<BigEndian>()? as usize;
+    let mut name_buf = vec![0u8; name_len];
+    stream.read_exact(&mut name_buf)?;
+    StoredName::from_wire(name_buf)
+}
 
+fn resolve_stored_file(requested_name: &StoredName) -> std::io::Result<Option<PathBuf>> {
     let canonical_uploads = ensure_uploads_dir()?;
-    let target_path = canonical_uploads.join(&requested_name);
+    let target_path = canonical_uploads.join(requested_name.as_os_str());
     let canonical_target = target_path
         .canonicalize()
-        .unwrap_or_else(|_| canonical_uploads.join(&requested_name));
+        .unwrap_or_else(|_| canonical_uploads.join(requested_name.as_os_str()));
 
     if !canonical_target.starts_with(&canonical_uploads) || !canonical_target.exists() {
-        stream.write_all(&[0u8])?;
-        let message = b"File not found";
-        stream.write_u16::<BigEndian>(message.len() as u16)?;
-        stream.write_all(message)?;
-        return Ok(());
+        return Ok(None);
+    }
+    let visible = canonical_target
+        .file_name()
+        .is_some_and(|name| !is_hidden_file(&name.to_string_lossy()));
+    if !visible {
+        return Ok(None);
     }
+    Ok(Some(canonical_target))
+}
+
+fn write_error_status(stream: &mut impl Transport, message: &str) -> std::io::Result<()> {
+    stream.write_all(&[0u8])?;
+    stream.write_u16::<BigEndian>(message.len() as u16)?;
+    stream.write_all(message.as_bytes())
+}
+
+fn handle_download(stream: &mut impl Transport) -> std::io::Result<()> {
+    let requested_name = read_stored_name(stream)?;
+    send_stored_file(stream, &requested_name, 0)
+}
+
+fn handle_resume_download(stream: &mut impl Transport) -> std::io::Result<()> {
+    let requested_name = read_stored_name(stream)?;
+    let offset = stream.read_u64::<BigEndian>()?;
+    send_stored_file(stream, &requested_name, offset)
+}
+
+/// Like `R`, but the client also sends the CRC-32 of the bytes it already has,
+/// so a corrupt partial file is not extended with fresh data.
+fn handle_verified_resume(stream: &mut impl Transport) -> std::io::Result<()> {
+    let requested_name = read_stored_name(stream)?;
+    let offset = stream.read_u64::<BigEndian>()?;
+    let client_crc = stream.read_u32::<BigEndian>()?;
+
+    if let Some(canonical_target) = resolve_stored_file(&requested_name)? {
+        let file = File::open(&canonical_target)?;
+        if offset <= file.metadata()?.len() && crc32_prefix(file, offset)? != client_crc {
+            println!("Refusing resume of '{}' at offset {}: prefix checksum mismatch", requested_name, offset);
+            stream.write_u8(STATUS_PREFIX_MISMATCH)?;
+            return Ok(());
+        }
+    }
+    send_stored_file(stream, &requested_name, offset)
+}
+
+/// Writes all of `chunk`, retrying timed-out writes until the client has gone
+/// `SLOW_CLIENT_GRACE` without accepting a single byte.
+fn write_chunk(stream: &mut impl Transport, chunk: &[u8]) -> std::io::Result<()> {
+    let mut written = 0;
+    let mut last_progress = Instant::now();
+    while written < chunk.len() {
+        match stream.write(&chunk[written..]) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                written += n;
+                last_progress = Instant::now();
+            }
+            Err(e) if is_timeout(&e) => {
+                if last_progress.elapsed() >= SLOW_CLIENT_GRACE {
+                    return Err(stalled_client("Download"));
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Sends `len` bytes of `file` starting at `offset` straight from the page cache
+/// to the socket. Returns how many bytes went out, or `None` when sendfile isn't
+/// available and nothing was sent, so the caller falls back to the buffered loop.
+#[cfg(target_os = "linux")]
+fn send_file_zero_copy(
+    stream: &impl Transport,
+    file: &File,
+    offset: u64,
+    len: u64,
+    transfer: &transfers::Transfer,
+) -> std::io::Result<Option<u64>> {
+    use std::os::fd::AsRawFd;
+
+    let Some(socket) = stream.raw_fd() else {
+        return Ok(None);
+    };
+    let mut file_offset = offset as libc::off_t;
+    let mut sent = 0u64;
+    let mut last_progress = Instant::now();
+    while sent < len {
+        let count = (len - sent).min(SENDFILE_CHUNK as u64) as usize;
+        let n = unsafe { libc::sendfile(socket, file.as_raw_fd(), &mut file_offset, count) };
+        if n < 0 {
+            let e = std::io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(libc::EINVAL | libc::ENOSYS) if sent == 0 => return Ok(None),
+                _ if is_timeout(&e) => {
+                    if last_progress.elapsed() >= SLOW_CLIENT_GRACE {
+                        return Err(stalled_client("Download"));
+                    }
+                }
+                _ if e.kind() == std::io::ErrorKind::Interrupted => {}
+                _ => return Err(e),
+            }
+            continue;
+        }
+        if n == 0 {
+            // The file got shorter while being sent; the size check reports it.
+            break;
+        }
+        sent += n as u64;
+        transfer.set_done(offset + sent);
+        last_progress = Instant::now();
+    }
+    Ok(Some(sent))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_file_zero_copy(
+    _stream: &impl Transport,
+    _file: &File,
+    _offset: u64,
+    _len: u64,
+    _transfer: &transfers::Transfer,
+) -> std::io::Result<Option<u64>> {
+    Ok(None)
+}
+
+fn send_stored_file(stream: &mut impl Transport, requested_name: &StoredName, offset: u64) -> std::io::Result<()> {
+    let Some(canonical_target) = resolve_stored_file(requested_name)? else {
+        return write_error_status(stream, "File not found");
+    };
 
     let mut file = File::open(&canonical_target)?;
-    let file_size = file.metadata()?.len();
+    let metadata = file.metadata()?;
+    let file_size = metadata.len();
+    if offset > file_size {
+        return write_error_status(
+            stream,
+            &format!("Offset {} is past the end of the file ({} bytes)", offset, file_size),
+        );
+    }
+
+    let cached = download_cache::get_or_load(&canonical_target, file_size, metadata.modified()?)?;
     stream.write_all(&[1u8])?;
     stream.write_u64::<BigEndian>(file_size)?;
 
-    let mut buffer = [0u8; 8192];
     let transfer_start = Instant::now();
+    let transfer = transfers::begin(transfers::Direction::Download, requested_name, offset, file_size);
     let mut total_written = 0u64;
-    loop {
-        let n = file.read(&mut buffer)?;
-        if n == 0 {
-            break;
+    if let Some(data) = cached {
+        for chunk in data[offset as usize..].chunks(8192) {
+            write_chunk(stream, chunk)?;
+            total_written += chunk.len() as u64;
+            transfer.set_done(offset + total_written);
+        }
+    } else if SENDFILE_ENABLED
+        && let Some(sent) = send_file_zero_copy(stream, &file, offset, file_size - offset, &transfer)?
+    {
+        total_written = sent;
+    } else {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buffer = [0u8; 8192];
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            write_chunk(stream, &buffer[..n])?;
+            total_written += n as u64;
+            transfer.set_done(offset + total_written);
         }
-        stream.write_all(&buffer[..n])?;
-        total_written += n as u64;
     }
 
     let elapsed = transfer_start.elapsed().as_secs_f64();
     let size_mb = total_written as f64 / (1024.0 * 1024.0);
     let speed = if elapsed > 0.0 { size_mb / elapsed } else { 0.0 };
     
-    if total_written != file_size {
+    if total_written != file_size - offset {
         println!("ERROR: Download incomplete for '{}': sent {} bytes, expected {} bytes",
-                  requested_name, total_written, file_size);
+                  requested_name, total_written, file_size - offset);
         return Ok(());
     }
     
-    println!(
-        "Sent '{}' -> {:.2} MB in {:.3} s ({:.2} MB/s)",
-        requested_name,
-        size_mb,
-        elapsed,
-        speed
-    );
+    if offset > 0 {
+        println!(
+            "Sent '{}' from offset {} -> {:.2} MB in {:.3} s ({:.2} MB/s)",
+            requested_name,
+            offset,
+            size_mb,
+            elapsed,
+            speed
+        );
+    } else {
+        println!(
+            "Sent '{}' -> {:.2} MB in {:.3} s ({:.2} MB/s)",
+            requested_name,
+            size_mb,
+            elapsed,
+            speed
+        );
+    }
     Ok(())
 }
 
-fn handle_list(stream: &mut TcpStream) -> std::io::Result<()> {
+/// Replies with the stored file's mode bits so a download can restore them.
+fn handle_stat_mode(stream: &mut impl Transport) -> std::io::Result<()> {
+    let requested_name = read_stored_name(stream)?;
+    let Some(canonical_target) = resolve_stored_file(&requested_name)? else {
+        return write_error_status(stream, "File not found");
+    };
+
+    let mode = file_mode(&canonical_target)?;
+    stream.write_u8(1)?;
+    stream.write_u32::<BigEndian>(mode)
+}
+
+/// Moves a stored file to another path below the uploads root, creating any
+/// missing directories on the way. An existing destination is only replaced
+/// when the client set `MOVE_FLAG_OVERWRITE`.
+fn handle_move(stream: &mut impl Transport) -> std::io::Result<()> {
+    let flags = stream.read_u8()?;
+    let source_name = read_requested_name(stream)?;
+    let destination_name = read_requested_name(stream)?;
+
+    match move_stored_file(&source_name, &destination_name, flags & MOVE_FLAG_OVERWRITE != 0)? {
+        None => {
+            println!("Moved '{}' -> '{}'", source_name, destination_name);
+            stream.write_u8(1)
+        }
+        Some(reason) => {
+            println!("Rejected move '{}' -> '{}': {}", source_name, destination_name, reason);
+            write_error_status(stream, &reason)
+        }
+    }
+}
+
+/// Returns why the move was refused, or `None` once it is done.
+fn move_stored_file(source_name: &str, destination_name: &str, overwrite: bool) -> std::io::Result<Option<String>> {
+    let (Some(source_relative), Some(destination_relative)) = (
+        sanitize::relative_upload_path(source_name),
+        sanitize::relative_upload_path(destination_name),
+    ) else {
+        return Ok(Some("Invalid path".to_string()));
+    };
+    let internal = |path: &Path| {
+        path.iter()
+            .any(|component| component.to_str().is_none_or(is_internal_file))
+    };
+    if internal(&source_relative) || internal(&destination_relative) {
+        return Ok(Some("Invalid path".to_string()));
+    }
+
     let canonical_uploads = ensure_uploads_dir()?;
-    let mut entries: Vec<(String, u64)> = Vec::new();
-    for entry in read_dir(&canonical_uploads)? {
-        if let Ok(entry) = entry {
-            if entry.file_type()?.is_file() {
-                if let Some(name) = entry.file_name().to_str() {
-                    let size = entry.metadata()?.len();
-                    entries.push((name.to_string(), size));
-                }
-            }
+    let Ok(source) = canonical_uploads.join(&source_relative).canonicalize() else {
+        return Ok(Some("File not found".to_string()));
+    };
+    if !source.starts_with(&canonical_uploads) || !source.is_file() {
+        return Ok(Some("File not found".to_string()));
+    }
+
+    let destination = canonical_uploads.join(&destination_relative);
+    if let Some(reason) = upload_path_too_long(
+        destination_relative.file_name().and_then(|name| name.to_str()).unwrap_or_default(),
+        &destination,
+    ) {
+        return Ok(Some(reason));
+    }
+    let Some(parent) = destination.parent() else {
+        return Ok(Some("Invalid path".to_string()));
+    };
+    if !parent.starts_with(&canonical_uploads) {
+        return Ok(Some("Invalid path".to_string()));
+    }
+    create_dir_all(parent)?;
+    if !parent.canonicalize()?.starts_with(&canonical_uploads) {
+        return Ok(Some("Invalid path".to_string()));
+    }
+
+    if let Ok(existing) = std::fs::symlink_metadata(&destination) {
+        if existing.is_dir() {
+            return Ok(Some("Destination is a directory".to_string()));
+        }
+        if !overwrite {
+            return Ok(Some("Destination already exists".to_string()));
+        }
+        if destination.canonicalize()? == source {
+            return Ok(None);
+        }
+    }
+
+    download_cache::invalidate(&source);
+    download_cache::invalidate(&destination);
+    match std::fs::rename(&source, &destination) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            std::fs::copy(&source, &destination)?;
+            std::fs::remove_file(&source)?;
+        }
+        other => other?,
+    }
+    dedup::moved(&canonical_uploads, &source, &destination)?;
+    Ok(None)
+}
+
+fn handle_head(stream: &mut impl Transport) -> std::io::Result<()> {
+    let requested_name = read_stored_name(stream)?;
+    let requested_len = stream.read_u64::<BigEndian>()?;
+
+    if requested_len > MAX_HEAD_BYTES {
+        return write_error_status(
+            stream,
+            &format!("Preview too large: {} bytes (max {})", requested_len, MAX_HEAD_BYTES),
+        );
+    }
+
+    let Some(canonical_target) = resolve_stored_file(&requested_name)? else {
+        return write_error_status(stream, "File not found");
+    };
+
+    let file = File::open(&canonical_target)?;
+    let length = requested_len.min(file.metadata()?.len());
+    stream.write_all(&[1u8])?;
+    stream.write_u64::<BigEndian>(length)?;
+
+    let mut head = file.take(length);
+    let sent = std::io::copy(&mut head, stream)?;
+    println!("Sent first {} bytes of '{}'", sent, requested_name);
+    Ok(())
+}
+
+fn list_uploads() -> std::io::Result<Vec<(Vec<u8>, u64)>> {
+    let canonical_uploads = ensure_uploads_dir()?;
+    let mut entries: Vec<(Vec<u8>, u64)> = Vec::new();
+    for entry in read_dir(&canonical_uploads)?.flatten() {
+        let name = entry.file_name();
+        if entry.file_type()?.is_file()
+            && !is_hidden_file(&name.to_string_lossy())
+            && let Some(bytes) = stored_name::wire_bytes(&name)
+        {
+            let size = entry.metadata()?.len();
+            entries.push((bytes, size));
         }
     }
+    Ok(entries)
+}
+
+fn handle_list(stream: &mut impl Transport) -> std::io::Result<()> {
+    let entries = list_uploads()?;
     stream.write_u16::<BigEndian>(entries.len() as u16)?;
-    for (name, size) in entries {
-        let bytes = name.as_bytes();
+    for (bytes, size) in entries {
         stream.write_u16::<BigEndian>(bytes.len() as u16)?;
-        stream.write_all(bytes)?;
+        stream.write_all(&bytes)?;
         stream.write_u64::<BigEndian>(size)?;
     }
     Ok(())
 }
 
-fn main() -> std::io::Result<()> {
-    let active_connections = Arc::new(Mutex::new(0usize));
-    
-    let listener = TcpListener::bind("127.0.0.1:4000")?;
-    println!("Listening on port 5000...");
-    println!("Max concurrent connections: {}", MAX_CONNECTIONS);
-    
+/// Snapshot of the uploads and downloads in flight on all connections: per
+/// transfer its direction, file name, bytes done, total bytes and milliseconds
+/// since it started.
+fn handle_transfers(stream: &mut impl Transport) -> std::io::Result<()> {
+    let transfers = transfers::snapshot();
+    stream.write_u16::<BigEndian>(transfers.len() as u16)?;
+    for transfer in transfers {
+        stream.write_u8(transfer.direction.wire_tag())?;
+        let bytes = transfer.file_name.as_bytes();
+        stream.write_u16::<BigEndian>(bytes.len() as u16)?;
+        stream.write_all(bytes)?;
+        stream.write_u64::<BigEndian>(transfer.done)?;
+        stream.write_u64::<BigEndian>(transfer.total)?;
+        stream.write_u64::<BigEndian>(transfer.started.elapsed().as_millis() as u64)?;
+    }
+    Ok(())
+}
+
+fn handle_typed_list(stream: &mut impl Transport) -> std::io::Result<()> {
+    write_typed_listing(stream, TYPED_LIST_VERSION)
+}
+
+/// `V` carries the highest listing version the client understands; the server
+/// answers with the highest version both sides support.
+fn handle_versioned_list(stream: &mut impl Transport) -> std::io::Result<()> {
+    let requested = stream.read_u8()?;
+    let version = requested.clamp(TYPED_LIST_VERSION, LISTING_STATUS_VERSION);
+    write_typed_listing(stream, version)
+}
+
+fn write_typed_listing(stream: &mut impl Transport, version: u8) -> std::io::Result<()> {
+    let (canonical_uploads, created) = ensure_uploads_dir_reporting_creation()?;
+    let mut files = read_dir(&canonical_uploads)?
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .filter(|entry| !is_hidden_file(&entry.file_name().to_string_lossy()))
+        .peekable();
+
+    stream.write_u8(version)?;
+    if version >= LISTING_STATUS_VERSION {
+        let status = if created {
+            LIST_STATUS_CREATED
+        } else if files.peek().is_none() {
+            LIST_STATUS_EMPTY
+        } else {
+            LIST_STATUS_POPULATED
+        };
+        stream.write_u8(status)?;
+    }
+
+    for entry in files {
+        let name = entry.file_name();
+        if let Some(bytes) = stored_name::wire_bytes(&name) {
+            let size = entry.metadata()?.len();
+            stream.write_u8(LIST_FRAME_ENTRY)?;
+            stream.write_u16::<BigEndian>(bytes.len() as u16)?;
+            stream.write_all(&bytes)?;
+            stream.write_u64::<BigEndian>(size)?;
+
+            let content_type = content_type_for(&name.to_string_lossy()).unwrap_or("");
+            stream.write_u8(content_type.len() as u8)?;
+            stream.write_all(content_type.as_bytes())?;
+        }
+    }
+    stream.write_u8(LIST_FRAME_END)?;
+    Ok(())
+}
+
+fn handle_capabilities(stream: &mut impl Transport) -> std::io::Result<()> {
+    let mut features = CAP_FILE_MODES
+        | CAP_RESUME
+        | CAP_VERIFIED_RESUME
+        | CAP_HEAD
+        | CAP_MOVE
+        | CAP_TRANSFERS
+        | CAP_INTEGRITY_REPORT
+        | CAP_PING;
+    if UPLOAD_ACK_CHECKSUM {
+        features |= CAP_UPLOAD_ACK_CHECKSUM;
+    }
+    if dedup::enabled() {
+        features |= CAP_DEDUP;
+    }
+    if READ_ONLY.load(Ordering::Relaxed) {
+        features |= CAP_READ_ONLY;
+    }
+    stream.write_u8(LISTING_STATUS_VERSION)?;
+    stream.write_u32::<BigEndian>(features)
+}
+
+/// Echoes the client's 8-byte nonce so it can time a round trip. Touches
+/// nothing on disk.
+fn handle_ping(stream: &mut impl Transport) -> std::io::Result<()> {
+    let nonce = stream.read_u64::<BigEndian>()?;
+    stream.write_u64::<BigEndian>(nonce)
+}
+
+/// Name, size, modification time (Unix seconds) and CRC-32 of every stored file,
+/// one frame per file as it is checksummed so nothing is buffered up front.
+/// A file that changes size while being read is left out.
+fn handle_integrity_report(stream: &mut impl Transport) -> std::io::Result<()> {
+    let canonical_uploads = ensure_uploads_dir()?;
+    let files = read_dir(&canonical_uploads)?
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .filter(|entry| !is_hidden_file(&entry.file_name().to_string_lossy()));
+
+    for entry in files {
+        let Some(bytes) = stored_name::wire_bytes(&entry.file_name()) else {
+            continue;
+        };
+        let name = String::from_utf8_lossy(&bytes).into_owned();
+        let metadata = entry.metadata()?;
+        let modified = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|age| age.as_secs())
+            .unwrap_or(0);
+        let crc = match File::open(entry.path()).and_then(|file| crc32_prefix(file, metadata.len())) {
+            Ok(crc) => crc,
+            Err(e) => {
+                println!("Left '{}' out of the integrity report: {}", name, e);
+                continue;
+            }
+        };
+
+        stream.write_u8(LIST_FRAME_ENTRY)?;
+        stream.write_u16::<BigEndian>(bytes.len() as u16)?;
+        stream.write_all(&bytes)?;
+        stream.write_u64::<BigEndian>(metadata.len())?;
+        stream.write_u64::<BigEndian>(modified)?;
+        stream.write_u32::<BigEndian>(crc)?;
+    }
+    stream.write_u8(LIST_FRAME_END)
+}
+
+/// Refuses a command that would change the uploads directory. An upload gets
+/// its reply line right away and the connection is closed, instead of reading
+/// a body only to throw it away.
+fn refuse_write(stream: &mut impl Transport, command: u8) -> std::io::Result<bool> {
+    println!("Refused '{}' command: server is read-only", command as char);
+    if command == b'M' {
+        stream.read_u8()?;
+        read_requested_name(stream)?;
+        read_requested_name(stream)?;
+        write_error_status(stream, READ_ONLY_REASON)?;
+        return Ok(true);
+    }
+    stream.write_all(format!("ERROR {}\n", READ_ONLY_REASON).as_bytes())?;
+    drain_before_close(stream);
+    Ok(false)
+}
+
+/// Tells a client over the connection limit why it is turned away.
+fn reject_busy(mut stream: impl Transport) {
+    let _ = stream.write_all(BUSY_RESPONSE);
+    drain_before_close(&mut stream);
+}
+
+/// Whatever the client already sent is drained for a moment after a final
+/// reply, otherwise closing the socket with unread data resets the connection
+/// before the client gets to read the reply.
+fn drain_before_close(stream: &mut impl Transport) {
+    let _ = stream.shutdown(Shutdown::Write);
+    let _ = stream.set_read_timeout(Some(BUSY_DRAIN_TIMEOUT));
+
+    let deadline = Instant::now() + BUSY_DRAIN_TIMEOUT;
+    let mut buffer = [0u8; 8192];
+    while Instant::now() < deadline {
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+}
+
+/// One of the `MAX_CONNECTIONS` slots, handed back when dropped so the count
+/// stays right even if the client thread panics or returns early.
+struct ConnectionSlot {
+    active_connections: Arc<Mutex<usize>>,
+}
+
+impl ConnectionSlot {
+    fn acquire(active_connections: &Arc<Mutex<usize>>) -> Option<Self> {
+        let mut count = active_connections.lock().unwrap();
+        if *count >= MAX_CONNECTIONS {
+            return None;
+        }
+        *count += 1;
+        Some(Self { active_connections: Arc::clone(active_connections) })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut count = self.active_connections.lock().unwrap_or_else(|e| e.into_inner());
+        *count = count.saturating_sub(1);
+    }
+}
+
+/// Hands an accepted connection its own thread, or turns it away while
+/// `MAX_CONNECTIONS` are already being served.
+fn accept_client<T: Transport + Send + 'static>(stream: T, active_connections: &Arc<Mutex<usize>>) {
+    let Some(slot) = ConnectionSlot::acquire(active_connections) else {
+        println!("Connection rejected: max limit ({}) reached", MAX_CONNECTIONS);
+        if let Err(e) = thread::Builder::new()
+            .name("fs-busy".to_string())
+            .stack_size(WORKER_STACK_SIZE.load(Ordering::Relaxed))
+            .spawn(move || reject_busy(stream))
+        {
+            println!("Failed to start busy reply thread: {:?}", e);
+        }
+        return;
+    };
+
+    let peer = stream.peer_label();
+    let worker = NEXT_WORKER_ID.fetch_add(1, Ordering::Relaxed);
+    println!("Client connected. id {} (fs-worker-{})", peer, worker);
+
+    let spawned = thread::Builder::new()
+        .name(format!("fs-worker-{}", worker))
+        .stack_size(WORKER_STACK_SIZE.load(Ordering::Relaxed))
+        .spawn(move || {
+            let _slot = slot;
+            if let Err(e) = handle_client(stream) {
+                println!("Client error: {:?}", e);
+            }
+            println!("Client disconnected. id {}", peer);
+        });
+    match spawned {
+        Ok(handle) => shutdown::register_worker(handle),
+        Err(e) => println!("Failed to start fs-worker-{}: {:?}", worker, e),
+    }
+}
+
+/// Binds a Unix domain socket at `path` for local clients, replacing a socket
+/// file left behind by an earlier run.
+#[cfg(unix)]
+fn bind_unix_socket(path: &Path) -> std::io::Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    println!("Listening on unix socket {:?}...", path);
+    Ok(listener)
+}
+
+#[cfg(unix)]
+fn serve_unix_listener(listener: UnixListener, active_connections: Arc<Mutex<usize>>) {
     for stream in listener.incoming() {
+        if shutdown::requested() {
+            break;
+        }
         match stream {
-            Ok(s) => {
-                let mut count = active_connections.lock().unwrap();
-                
-                if *count >= MAX_CONNECTIONS {
-                    println!("Connection rejected: max limit ({}) reached", MAX_CONNECTIONS);
-                    drop(count);
-                    drop(s);
-                    continue;
-                }
-                
-                *count += 1;
-                drop(count);
-                
-                println!("Client connected. id {}", &s.peer_addr().unwrap());
-                
-                let counter = Arc::clone(&active_connections);
-                thread::spawn(move || {
-                    let ip = s.peer_addr().unwrap();
-                    if let Err(e) = handle_client(s) {
-                        println!("Client error: {:?}", e);
-                    }
-                    
-                    let mut count = counter.lock().unwrap();
-                    *count -= 1;
-                    println!("Client disconnected. id {}", ip);
-                });
-            }
+            Ok(s) => accept_client(s, &active_connections),
+            Err(e) => println!("Connection failed: {:?}", e),
+        }
+    }
+}
+
+fn serve_tcp_listener(listener: TcpListener, active_connections: Arc<Mutex<usize>>) {
+    for stream in listener.incoming() {
+        if shutdown::requested() {
+            break;
+        }
+        match stream {
+            Ok(s) => accept_client(s, &active_connections),
             Err(e) => println!("Connection failed: {:?}", e),
         }
     }
+}
+
+fn usage_error(message: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("{message}\nUsage: server [--listen <addr>]... [--unix-socket <path>] [--no-tcp] [--max-files <count> [--reject-when-full]] [--max-name-bytes <bytes>] [--max-path-len <chars>] [--dedup] [--read-only] [--show-hidden] [--no-download-cache] [--worker-stack-size <bytes>]")
+    )
+}
+
+fn main() -> std::io::Result<()> {
+    let active_connections = Arc::new(Mutex::new(0usize));
+
+    let mut listen_addrs: Vec<String> = Vec::new();
+    let mut unix_socket: Option<PathBuf> = None;
+    let mut serve_tcp = true;
+    let mut max_files: Option<usize> = None;
+    let mut when_full = storage_limit::WhenFull::EvictOldest;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => {
+                let addr = args.next().ok_or_else(|| usage_error("--listen needs an address"))?;
+                listen_addrs.push(addr);
+            }
+            "--unix-socket" => {
+                let path = args.next().ok_or_else(|| usage_error("--unix-socket needs a path"))?;
+                unix_socket = Some(PathBuf::from(path));
+            }
+            "--no-tcp" => serve_tcp = false,
+            "--max-files" => {
+                let count = args
+                    .next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .filter(|&count| count > 0)
+                    .ok_or_else(|| usage_error("--max-files needs a positive count"))?;
+                max_files = Some(count);
+            }
+            "--reject-when-full" => when_full = storage_limit::WhenFull::Reject,
+            "--max-name-bytes" => {
+                let bytes = args
+                    .next()
+                    .and_then(|bytes| bytes.parse::<usize>().ok())
+                    .filter(|&bytes| bytes > 0)
+                    .ok_or_else(|| usage_error("--max-name-bytes needs a positive byte count"))?;
+                MAX_UPLOAD_NAME_BYTES.store(bytes, Ordering::Relaxed);
+            }
+            "--max-path-len" => {
+                let len = args
+                    .next()
+                    .and_then(|len| len.parse::<usize>().ok())
+                    .filter(|&len| len > 0)
+                    .ok_or_else(|| usage_error("--max-path-len needs a positive length"))?;
+                MAX_UPLOAD_PATH_LEN.store(len, Ordering::Relaxed);
+            }
+            "--dedup" => dedup::enable(),
+            "--read-only" => READ_ONLY.store(true, Ordering::Relaxed),
+            "--show-hidden" => SHOW_HIDDEN_FILES.store(true, Ordering::Relaxed),
+            "--no-download-cache" => download_cache::disable(),
+            "--worker-stack-size" => {
+                let size = args
+                    .next()
+                    .and_then(|size| size.parse::<usize>().ok())
+                    .filter(|&size| size >= MIN_WORKER_STACK_SIZE)
+                    .ok_or_else(|| {
+                        usage_error(&format!("--worker-stack-size needs a size of at least {} bytes", MIN_WORKER_STACK_SIZE))
+                    })?;
+                WORKER_STACK_SIZE.store(size, Ordering::Relaxed);
+            }
+            other => return Err(usage_error(&format!("Unknown argument: {other}"))),
+        }
+    }
+
+    if !serve_tcp && unix_socket.is_none() {
+        return Err(usage_error("--no-tcp needs --unix-socket"));
+    }
+    if !serve_tcp && !listen_addrs.is_empty() {
+        return Err(usage_error("--no-tcp can't be combined with --listen"));
+    }
+    if listen_addrs.is_empty() {
+        listen_addrs.push(DEFAULT_LISTEN_ADDR.to_string());
+    }
+    if let Some(max_files) = max_files {
+        storage_limit::configure(storage_limit::StorageLimit { max_files, when_full });
+    } else if when_full == storage_limit::WhenFull::Reject {
+        return Err(usage_error("--reject-when-full needs --max-files"));
+    }
+    #[cfg(not(unix))]
+    if unix_socket.is_some() {
+        return Err(usage_error("Unix domain sockets are not supported on this platform"));
+    }
+
+    let shutdown_requested = shutdown::install()?;
+
+    // Bind everything before serving anything, so a bad address fails the start.
+    let mut listeners = Vec::new();
+    if serve_tcp {
+        for addr in &listen_addrs {
+            let listener = TcpListener::bind(addr)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("Failed to listen on {}: {}", addr, e)))?;
+            listeners.push(listener);
+        }
+    }
+    #[cfg(unix)]
+    let unix_listener = unix_socket.as_deref().map(bind_unix_socket).transpose()?;
+    print_startup_banner(&listeners, unix_socket.as_deref(), max_files.map(|max| (max, when_full)))?;
+
+    #[cfg(unix)]
+    if let Some(listener) = unix_listener {
+        let counter = Arc::clone(&active_connections);
+        thread::Builder::new()
+            .name("fs-accept-unix".to_string())
+            .spawn(move || serve_unix_listener(listener, counter))?;
+    }
+
+    // All listeners share `active_connections`, so `MAX_CONNECTIONS` is a
+    // server-wide cap.
+    for (index, listener) in listeners.into_iter().enumerate() {
+        let counter = Arc::clone(&active_connections);
+        thread::Builder::new()
+            .name(format!("fs-accept-{}", index))
+            .spawn(move || serve_tcp_listener(listener, counter))?;
+    }
+
+    let _ = shutdown_requested.recv();
+    println!("Shutting down: no new connections, waiting up to {:?} for active ones", SHUTDOWN_GRACE);
+    let still_running = shutdown::wait_for_workers(SHUTDOWN_GRACE);
+    if still_running > 0 {
+        println!("{} connection(s) still active, exiting anyway", still_running);
+        for transfer in transfers::snapshot() {
+            println!(
+                "  interrupted {:?} of '{}' at {} of {} bytes",
+                transfer.direction, transfer.file_name, transfer.done, transfer.total
+            );
+        }
+    }
+    if let Some(path) = &unix_socket {
+        let _ = std::fs::remove_file(path);
+    }
+    println!("Server stopped");
+    Ok(())
+}
+
+/// One block saying what is running and how it is configured, printed once the
+/// TCP listeners are bound.
+fn print_startup_banner(
+    listeners: &[TcpListener],
+    unix_socket: Option<&Path>,
+    max_files: Option<(usize, storage_limit::WhenFull)>,
+) -> std::io::Result<()> {
+    let mut addrs = Vec::new();
+    for listener in listeners {
+        addrs.push(listener.local_addr()?.to_string());
+    }
+
+    println!("=== file server {} ===", env!("CARGO_PKG_VERSION"));
+    println!("  listen:          {}", if addrs.is_empty() { "-".to_string() } else { addrs.join(", ") });
+    println!(
+        "  unix socket:     {}",
+        unix_socket.map_or_else(|| "-".to_string(), |path| path.display().to_string())
+    );
+    println!("  uploads dir:     {}", UPLOADS_DIR);
+    println!("  max connections: {}", MAX_CONNECTIONS);
+    println!("  worker stack:    {} KiB", WORKER_STACK_SIZE.load(Ordering::Relaxed) / 1024);
+    match max_files {
+        Some((max_files, when_full)) => println!("  max files:       {} ({:?} when full)", max_files, when_full),
+        None => println!("  max files:       unlimited"),
+    }
+    println!(
+        "  upload limits:   {} byte names, {} character paths",
+        MAX_UPLOAD_NAME_BYTES.load(Ordering::Relaxed),
+        MAX_UPLOAD_PATH_LEN.load(Ordering::Relaxed)
+    );
+    println!("  dedup:           {}", if dedup::enabled() { "on" } else { "off" });
+    println!("  read-only:       {}", if READ_ONLY.load(Ordering::Relaxed) { "yes" } else { "no" });
+    println!("  hidden files:    {}", if SHOW_HIDDEN_FILES.load(Ordering::Relaxed) { "shown" } else { "hidden" });
+    println!("  download cache:  {}", if download_cache::enabled() { "on" } else { "off" });
+    println!("  sendfile:        {}", if SENDFILE_ENABLED { "on" } else { "off" });
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A client connection whose requests are queued up front and whose replies
+    /// are collected in memory.
+    struct MemoryStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MemoryStream {
+        fn new(input: Vec<u8>) -> Self {
+            Self { input: Cursor::new(input), output: Vec::new() }
+        }
+    }
+
+    impl Read for MemoryStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MemoryStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for MemoryStream {
+        fn set_read_timeout(&self, _timeout: Option<Duration>) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn set_write_timeout(&self, _timeout: Option<Duration>) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn shutdown(&self, _how: Shutdown) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl client_api::Transport for MemoryStream {
+        fn peer_label(&self) -> String {
+            "memory".to_string()
+        }
+    }
+
+    fn name_frame(name: &str) -> Vec<u8> {
+        let mut frame = Vec::new();
+        frame.write_u16::<BigEndian>(name.len() as u16).unwrap();
+        frame.extend_from_slice(name.as_bytes());
+        frame
+    }
+
+    #[test]
+    fn resume_returns_the_rest_of_the_file() {
+        let name = format!("resume-test-{}.bin", std::process::id());
+        let path = ensure_uploads_dir().unwrap().join(&name);
+        let file: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &file).unwrap();
+
+        // The second request is served from the download cache, the first from disk.
+        for _ in 0..2 {
+            let mut request = name_frame(&name);
+            request.write_u64::<BigEndian>(file.len() as u64 / 2).unwrap();
+            let mut stream = MemoryStream::new(request);
+            handle_resume_download(&mut stream).unwrap();
+
+            let mut reply = Cursor::new(stream.output);
+            assert_eq!(reply.read_u8().unwrap(), 1);
+            assert_eq!(reply.read_u64::<BigEndian>().unwrap(), file.len() as u64);
+            let mut body = Vec::new();
+            reply.read_to_end(&mut body).unwrap();
+            assert_eq!(body, file[file.len() / 2..]);
+        }
+
+        download_cache::invalidate(&path);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn upload_with_too_long_name_is_rejected() {
+        let name = "n".repeat(DEFAULT_MAX_UPLOAD_NAME_BYTES + 1);
+        let body = b"never stored";
+        let mut request = name_frame(&name);
+        request.write_u64::<BigEndian>(body.len() as u64).unwrap();
+        request.extend_from_slice(body);
+        let mut stream = MemoryStream::new(request);
+        handle_upload(&mut stream, false).unwrap();
+
+        let reply = String::from_utf8(stream.output).unwrap();
+        assert!(reply.starts_with("ERROR File name too long"), "{reply}");
+        assert!(!ensure_uploads_dir().unwrap().join(&name).exists());
+    }
+
+    #[test]
+    fn case_only_collision_finds_the_existing_spelling() {
+        let dir = std::env::temp_dir().join(format!("case-collision-test-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Report.pdf"), b"old").unwrap();
+
+        assert_eq!(case_only_collision(&dir, "Report.pdf").unwrap(), None);
+        assert_eq!(case_only_collision(&dir, "other.pdf").unwrap(), None);
+        // Only a case-insensitive file system resolves both spellings to one file.
+        let case_insensitive = dir.join("report.pdf").exists();
+        assert_eq!(
+            case_only_collision(&dir, "report.pdf").unwrap(),
+            case_insensitive.then(|| "Report.pdf".to_string())
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn upload_with_internal_name_is_rejected() {
+        let uploads = ensure_uploads_dir().unwrap();
+        for name in [".dedup-index", ".blobs", "report.pdf.partial"] {
+            let body = b"not server state";
+            let mut request = name_frame(name);
+            request.write_u64::<BigEndian>(body.len() as u64).unwrap();
+            request.extend_from_slice(body);
+            let mut stream = MemoryStream::new(request);
+            handle_upload(&mut stream, false).unwrap();
+
+            assert_eq!(stream.output, b"ERROR Invalid file name\n");
+            assert_eq!(stream.input.position(), stream.input.get_ref().len() as u64);
+            assert!(!uploads.join(name).exists());
+        }
+    }
+
+    #[test]
+    fn upload_with_too_long_path_is_rejected() {
+        let deep = Path::new("uploads").join("d/".repeat(DEFAULT_MAX_UPLOAD_PATH_LEN / 2));
+        let reason = upload_path_too_long("a.txt", &deep.join("a.txt")).unwrap();
+        assert!(reason.starts_with("File path too long"), "{reason}");
+        assert_eq!(upload_path_too_long("a.txt", Path::new("uploads/a.txt")), None);
+    }
+}
@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Turns a client-supplied upload name into a safe, plain file name: decodes
+/// `%XX` escapes, keeps only the last path component, drops control characters
+/// and replaces characters Windows reserves with `_`. Returns `None` when
+/// nothing usable is left.
+pub fn sanitize_file_name(raw: &str) -> Option<String> {
+    let decoded = percent_decode(raw);
+    let normalized = decoded.replace('\\', "/");
+    let base = Path::new(&normalized).file_name()?.to_str()?;
+
+    let cleaned: String = base
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if RESERVED_CHARS.contains(&c) { '_' } else { c })
+        .collect();
+    let cleaned = cleaned.trim().trim_end_matches('.');
+
+    if cleaned.is_empty() || cleaned == "." || cleaned == ".." {
+        return None;
+    }
+    Some(cleaned.to_string())
+}
+
+/// Checks a client-supplied path relative to the uploads root (`/` or `\`
+/// separated). Unlike [`sanitize_file_name`] nothing is rewritten: a path with an
+/// empty, `.` or `..` component, or characters an upload name couldn't have, is
+/// refused with `None`.
+pub fn relative_upload_path(raw: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in raw.split(['/', '\\']) {
+        if sanitize_file_name(component).as_deref() != Some(component) {
+            return None;
+        }
+        path.push(component);
+    }
+    Some(path)
+}
+
+fn percent_decode(raw: &str) -> String {
+    let bytes = raw.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && let (Some(hi), Some(lo)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2]))
+        {
+            out.push(hi << 4 | lo);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(out).unwrap_or_else(|_| raw.to_string())
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_name_is_kept() {
+        assert_eq!(sanitize_file_name("report 2024.txt").as_deref(), Some("report 2024.txt"));
+    }
+
+    #[test]
+    fn encoded_traversal_keeps_only_the_last_component() {
+        assert_eq!(sanitize_file_name("%2e%2e%2f"), None);
+        assert_eq!(sanitize_file_name("%2e%2e%2f%2e%2e%2fetc%2fpasswd").as_deref(), Some("passwd"));
+        assert_eq!(sanitize_file_name("..%5c..%5cboot.ini").as_deref(), Some("boot.ini"));
+    }
+
+    #[test]
+    fn encoded_nul_is_dropped() {
+        assert_eq!(sanitize_file_name("%00"), None);
+        assert_eq!(sanitize_file_name("evil%00.txt").as_deref(), Some("evil.txt"));
+    }
+
+    #[test]
+    fn invalid_escape_is_left_as_is() {
+        assert_eq!(sanitize_file_name("%zz").as_deref(), Some("%zz"));
+        assert_eq!(sanitize_file_name("100%").as_deref(), Some("100%"));
+    }
+
+    #[test]
+    fn relative_path_refuses_traversal() {
+        assert_eq!(relative_upload_path("docs/a.txt"), Some(PathBuf::from("docs").join("a.txt")));
+        assert_eq!(relative_upload_path("docs/../a.txt"), None);
+        assert_eq!(relative_upload_path("%2e%2e/a.txt"), None);
+    }
+}
@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{LazyLock, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Set once Ctrl-C (or SIGTERM) arrives: accept loops stop taking connections
+/// and workers close theirs after the command in progress.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static WORKERS: LazyLock<Mutex<Vec<JoinHandle<()>>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+pub fn requested() -> bool {
+    SHUTDOWN.load(Ordering::Relaxed)
+}
+
+/// Installs the signal handler. The returned receiver gets a message when a
+/// shutdown is requested.
+pub fn install() -> std::io::Result<Receiver<()>> {
+    let (tx, rx) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        if !SHUTDOWN.swap(true, Ordering::Relaxed) {
+            let _ = tx.send(());
+        }
+    })
+    .map_err(std::io::Error::other)?;
+    Ok(rx)
+}
+
+/// Keeps a connection thread's handle so [`wait_for_workers`] can join it.
+/// Handles of threads that already finished are dropped on the way.
+pub fn register_worker(handle: JoinHandle<()>) {
+    let mut workers = WORKERS.lock().unwrap_or_else(|e| e.into_inner());
+    workers.retain(|worker| !worker.is_finished());
+    workers.push(handle);
+}
+
+/// Joins the registered connection threads, giving up after `timeout`.
+/// Returns how many were still running then.
+pub fn wait_for_workers(timeout: Duration) -> usize {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let mut workers = WORKERS.lock().unwrap_or_else(|e| e.into_inner());
+        let (finished, running): (Vec<_>, Vec<_>) = workers.drain(..).partition(|worker| worker.is_finished());
+        *workers = running;
+        let remaining = workers.len();
+        drop(workers);
+
+        for worker in finished {
+            let _ = worker.join();
+        }
+        if remaining == 0 || Instant::now() >= deadline {
+            return remaining;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
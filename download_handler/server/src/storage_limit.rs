@@ -0,0 +1,122 @@
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+use crate::{dedup, download_cache, is_internal_file};
+
+/// What to do with an upload that would push the uploads directory past its limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhenFull {
+    /// Store it and delete the least recently modified files to get back under the limit.
+    EvictOldest,
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StorageLimit {
+    pub max_files: usize,
+    pub when_full: WhenFull,
+}
+
+static STORAGE_LIMIT: OnceLock<StorageLimit> = OnceLock::new();
+/// Held while counting and evicting so concurrent uploads don't both evict.
+static STORAGE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Enables the limit; without a call the number of stored files is unbounded.
+pub fn configure(limit: StorageLimit) {
+    let _ = STORAGE_LIMIT.set(limit);
+}
+
+/// Checked before an upload to `target` is received. Returns the reason to
+/// refuse it when the directory is full and full means reject.
+pub fn admit(uploads_dir: &Path, target: &Path) -> std::io::Result<Option<String>> {
+    let Some(limit) = STORAGE_LIMIT.get() else {
+        return Ok(None);
+    };
+    if limit.when_full != WhenFull::Reject || target.exists() {
+        return Ok(None);
+    }
+
+    let _guard = STORAGE_LOCK.lock().unwrap();
+    let stored = stored_files(uploads_dir)?.len();
+    if stored >= limit.max_files {
+        return Ok(Some(format!("Storage full ({} of {} files)", stored, limit.max_files)));
+    }
+    Ok(None)
+}
+
+/// Called once `keep` has been stored: evicts the oldest other files by
+/// modification time until the directory is back within the limit.
+pub fn enforce(uploads_dir: &Path, keep: &Path) -> std::io::Result<()> {
+    let Some(limit) = STORAGE_LIMIT.get() else {
+        return Ok(());
+    };
+    if limit.when_full != WhenFull::EvictOldest {
+        return Ok(());
+    }
+
+    let _guard = STORAGE_LOCK.lock().unwrap();
+    let mut files = stored_files(uploads_dir)?;
+    if files.len() <= limit.max_files {
+        return Ok(());
+    }
+
+    files.retain(|(path, _)| path != keep);
+    files.sort_by_key(|(_, modified)| *modified);
+    // `keep` itself still counts towards the limit.
+    let excess = (files.len() + 1).saturating_sub(limit.max_files);
+    for (path, _) in files.into_iter().take(excess) {
+        download_cache::invalidate(&path);
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                println!("Evicted {:?} to stay within {} stored files", path, limit.max_files);
+                dedup::forget(uploads_dir, &path)?;
+            }
+            Err(e) => println!("Failed to evict {:?}: {}", path, e),
+        }
+    }
+    Ok(())
+}
+
+/// Regular files directly in `uploads_dir` that were uploaded by users. Internal
+/// files (dotfiles, sidecars), directories and symlinks are never counted or
+/// evicted, even when `--show-hidden` lists them.
+fn stored_files(uploads_dir: &Path) -> std::io::Result<Vec<(PathBuf, SystemTime)>> {
+    let mut files = Vec::new();
+    for entry in read_dir(uploads_dir)?.flatten() {
+        let Ok(metadata) = entry.path().symlink_metadata() else {
+            continue;
+        };
+        let path = entry.path();
+        if metadata.is_file()
+            && path.parent() == Some(uploads_dir)
+            && !is_internal_file(&entry.file_name().to_string_lossy())
+        {
+            files.push((path, metadata.modified()?));
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn internal_files_are_not_counted_even_when_shown() {
+        let dir = std::env::temp_dir().join(format!("storage-limit-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["a.txt", ".dedup-index", ".dedup-index.tmp", "a.txt.partial"] {
+            std::fs::write(dir.join(name), b"x").unwrap();
+        }
+
+        crate::SHOW_HIDDEN_FILES.store(true, Ordering::Relaxed);
+        let stored = stored_files(&dir).unwrap();
+        crate::SHOW_HIDDEN_FILES.store(false, Ordering::Relaxed);
+        assert_eq!(stored.into_iter().map(|(path, _)| path).collect::<Vec<_>>(), [dir.join("a.txt")]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
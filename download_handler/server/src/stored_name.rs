@@ -0,0 +1,60 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::ops::Deref;
+
+/// A stored file's name as it travels on the wire. On Unix that is the raw
+/// bytes, so files whose names aren't valid UTF-8 are still listed and can be
+/// requested byte for byte; elsewhere names are always UTF-8.
+pub fn wire_bytes(name: &OsStr) -> Option<Vec<u8>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Some(name.as_bytes().to_vec())
+    }
+    #[cfg(not(unix))]
+    {
+        name.to_str().map(|name| name.as_bytes().to_vec())
+    }
+}
+
+/// A file name a client asked for: the exact name to open, and a printable
+/// rendering for logs and anything that needs a `&str` (which it derefs to).
+pub struct StoredName {
+    os: OsString,
+    display: String,
+}
+
+impl StoredName {
+    pub fn from_wire(bytes: Vec<u8>) -> std::io::Result<Self> {
+        match String::from_utf8(bytes) {
+            Ok(name) => Ok(Self { os: OsString::from(&name), display: name }),
+            #[cfg(unix)]
+            Err(e) => {
+                use std::os::unix::ffi::OsStringExt;
+                let bytes = e.into_bytes();
+                let display = String::from_utf8_lossy(&bytes).into_owned();
+                Ok(Self { os: OsString::from_vec(bytes), display })
+            }
+            #[cfg(not(unix))]
+            Err(_) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid UTF-8")),
+        }
+    }
+
+    pub fn as_os_str(&self) -> &OsStr {
+        &self.os
+    }
+}
+
+impl Deref for StoredName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.display
+    }
+}
+
+impl fmt::Display for StoredName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.display)
+    }
+}
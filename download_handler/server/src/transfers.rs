@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+impl Direction {
+    /// The byte a transfer snapshot uses on the wire.
+    pub fn wire_tag(self) -> u8 {
+        match self {
+            Direction::Upload => b'U',
+            Direction::Download => b'D',
+        }
+    }
+}
+
+struct Entry {
+    direction: Direction,
+    file_name: String,
+    total: u64,
+    started: Instant,
+    done: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransferSnapshot {
+    pub direction: Direction,
+    pub file_name: String,
+    pub done: u64,
+    pub total: u64,
+    pub started: Instant,
+}
+
+static TRANSFERS: LazyLock<Mutex<HashMap<u64, Entry>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+static NEXT_TRANSFER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A transfer in flight. Progress updates don't take the registry lock, and the
+/// entry disappears when this is dropped, however the transfer ended.
+pub struct Transfer {
+    id: u64,
+    done: Arc<AtomicU64>,
+}
+
+impl Transfer {
+    pub fn set_done(&self, done: u64) {
+        self.done.store(done, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        TRANSFERS.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.id);
+    }
+}
+
+/// Registers a transfer of `total` bytes of which `done` are already there
+/// (non-zero when a download resumes).
+pub fn begin(direction: Direction, file_name: &str, done: u64, total: u64) -> Transfer {
+    let id = NEXT_TRANSFER_ID.fetch_add(1, Ordering::Relaxed);
+    let done = Arc::new(AtomicU64::new(done));
+    TRANSFERS.lock().unwrap().insert(id, Entry {
+        direction,
+        file_name: file_name.to_string(),
+        total,
+        started: Instant::now(),
+        done: Arc::clone(&done),
+    });
+    Transfer { id, done }
+}
+
+/// Transfers currently in flight, oldest first.
+pub fn snapshot() -> Vec<TransferSnapshot> {
+    let mut transfers: Vec<TransferSnapshot> = TRANSFERS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|entry| TransferSnapshot {
+            direction: entry.direction,
+            file_name: entry.file_name.clone(),
+            done: entry.done.load(Ordering::Relaxed),
+            total: entry.total,
+            started: entry.started,
+        })
+        .collect();
+    transfers.sort_by_key(|transfer| transfer.started);
+    transfers
+}
@@ -1,34 +1,154 @@
-use client_api::{upload_file, download_file, fetch_available_files, RemoteFileInfo};
+use client_api::{upload_file_with_retry, download_file_with_retry, download_file_by_raw_name, download_file_expecting_size, resume_download_with_retry, partial_download_size, is_file_changed, fetch_listing, ping, BusyRetry, RemoteListing, SpeedSmoother, DEFAULT_SPEED_SMOOTHING};
 use std::{env, path::Path};
 use tauri::{AppHandle, Emitter};
 use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
 pub static APP_HANDLE: OnceLock<AppHandle> = OnceLock::new();
 
+/// How often the file list is requested before the error reaches the UI.
+const LISTING_RETRY_ATTEMPTS: u32 = 3;
+/// Delay before the second attempt; doubled after every further one.
+const LISTING_RETRY_BACKOFF: Duration = Duration::from_millis(300);
+
+#[derive(serde::Serialize)]
+struct ListingRetry {
+    attempt: u32,
+    attempts: u32,
+    delay_ms: u64,
+    error: String,
+}
+
 #[tauri::command]
 async fn get_available_files(
     server_ip: &str, 
-    server_port: &str
-) -> Result<Vec<RemoteFileInfo>, String> {
+    server_port: &str,
+    retry_attempts: Option<u32>,
+) -> Result<RemoteListing, String> {
     let server_addr = format!("{}:{}", server_ip, server_port);
-    let available_files = fetch_available_files(&server_addr);
-    match available_files {
-        Ok(files) => Ok(files),
-        Err(e) => Err(format!("Failed to fetch files: {}", e)),
+    let attempts = retry_attempts.unwrap_or(LISTING_RETRY_ATTEMPTS).max(1);
+    let mut backoff = LISTING_RETRY_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match fetch_listing(&server_addr) {
+            Ok(listing) => return Ok(listing),
+            Err(e) if attempt < attempts => {
+                let app_handle: &AppHandle = APP_HANDLE.get().expect("AppHandle not initialized");
+                let payload = ListingRetry {
+                    attempt,
+                    attempts,
+                    delay_ms: backoff.as_millis() as u64,
+                    error: e.to_string(),
+                };
+                app_handle.emit("listing_retry", &payload).unwrap();
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(format!("Failed to fetch files: {}", e)),
+        }
     }
 }
 
+/// Round trip to the server in milliseconds.
+#[tauri::command]
+async fn ping_server(server_ip: &str, server_port: &str) -> Result<f64, String> {
+    let server_addr = format!("{}:{}", server_ip, server_port);
+    ping(&server_addr)
+        .map(|rtt| rtt.as_secs_f64() * 1000.0)
+        .map_err(|e| format!("Ping failed: {}", e))
+}
+
 #[tauri::command]
 async fn download_file_front(
     server_ip: &str,
     server_port: &str,
     file_name: &str,
+    raw_name: Option<Vec<u8>>,
+    expected_size: Option<u64>,
+    speed_smoothing: Option<f64>,
 ) -> Result<String, String> {
-    let _ = env::home_dir()
+    let server_addr = format!("{}:{}", server_ip, server_port);
+    let _ = download_to_downloads_dir(&server_addr, file_name, raw_name.as_deref(), expected_size, speed_smoothing, |_| {});
+
+    Ok("Download initiated".to_string())
+}
+
+/// A file picked for download: its listed name, plus the exact bytes when
+/// that name is a lossy rendering (see `RemoteFileInfo::raw_name`), and the
+/// size it was listed with.
+#[derive(serde::Deserialize)]
+struct RequestedFile {
+    name: String,
+    raw_name: Option<Vec<u8>>,
+    expected_size: Option<u64>,
+}
+
+/// Downloads the selected files one after another into ~/Downloads. A failed
+/// file is recorded in the summary and the rest of the batch still runs.
+#[tauri::command]
+async fn download_files_front(
+    server_ip: &str,
+    server_port: &str,
+    files: Vec<RequestedFile>,
+    speed_smoothing: Option<f64>,
+) -> Result<BatchSummary, String> {
+    let server_addr = format!("{}:{}", server_ip, server_port);
+    let total = files.len();
+    let mut summary = BatchSummary {
+        succeeded: Vec::new(),
+        failed: Vec::new(),
+    };
+
+    for (completed, RequestedFile { name: file_name, raw_name, expected_size }) in files.into_iter().enumerate() {
+        emit_batch_progress(completed, total, &file_name, 0.0);
+        let result = download_to_downloads_dir(&server_addr, &file_name, raw_name.as_deref(), expected_size, speed_smoothing, |progress| {
+            emit_batch_progress(completed, total, &file_name, progress);
+        });
+        match result {
+            Ok(_) => summary.succeeded.push(file_name),
+            Err(error) => {
+                println!("{}", error);
+                summary.failed.push(BatchFailure { name: file_name, error });
+            }
+        }
+    }
+
+    println!(
+        "Batch download finished: {} succeeded, {} failed",
+        summary.succeeded.len(),
+        summary.failed.len()
+    );
+    Ok(summary)
+}
+
+/// `on_progress` gets the file's progress in percent along with every
+/// `download_progress` event. With `raw_name` the server is asked for those
+/// exact bytes, and the local copy is saved under the printable `file_name`.
+/// With `expected_size` a file that changed since it was listed is refused and
+/// `file_changed` is emitted so the list can be refreshed. What an earlier,
+/// failed attempt left in the `.partial` file is resumed after, once the server
+/// confirmed those bytes still match.
+fn download_to_downloads_dir(
+    server_addr: &str,
+    file_name: &str,
+    raw_name: Option<&[u8]>,
+    expected_size: Option<u64>,
+    speed_smoothing: Option<f64>,
+    mut on_progress: impl FnMut(f64),
+) -> Result<String, String> {
+    env::home_dir()
         .map(|home| {
-            let server_addr = format!("{}:{}", server_ip, server_port);
             let destination = home.join("Downloads").join(file_name);
-            let result = download_file(file_name, &destination, &server_addr, |progress, instant, avg, time: f64| {
+            let started = Instant::now();
+            let mut last_avg = 0.0;
+            let mut smoother = SpeedSmoother::new(speed_smoothing.unwrap_or(DEFAULT_SPEED_SMOOTHING));
+            let resume_from = match raw_name {
+                Some(_) => 0,
+                None => partial_download_size(&destination).unwrap_or(0),
+            };
+            emit_transfer_started(file_name, "download", resume_from);
+            let report_progress = |progress, instant, avg, time: f64| {
                 let app_handle: &AppHandle = APP_HANDLE.get().expect("AppHandle not initialized");
                 let file_name = destination
                         .file_name()
@@ -39,13 +159,59 @@ async fn download_file_front(
                     name: file_name,
                     progress,
                     instant,
+                    instant_smoothed: smoother.update(instant),
                     avg,
                     time
                 };
+                last_avg = avg;
 
                 app_handle.emit("download_progress", &response).unwrap();
+                on_progress(progress);
                 println!("Progress: {:6.2}% | Now: {:6.2} MB/s | Avg: {:6.2} MB/s | File: {}", progress, instant, avg, response.name);
-            });
+            };
+            let on_busy = |delay| emit_server_busy(file_name, "download", delay);
+            let result = match (raw_name, expected_size) {
+                (Some(raw_name), expected_size) => download_file_by_raw_name(
+                    raw_name,
+                    &destination,
+                    server_addr,
+                    expected_size,
+                    &BusyRetry::default(),
+                    on_busy,
+                    report_progress,
+                ),
+                (None, _) if resume_from > 0 => resume_download_with_retry(
+                    file_name,
+                    &destination,
+                    server_addr,
+                    expected_size,
+                    &BusyRetry::default(),
+                    on_busy,
+                    report_progress,
+                ),
+                (None, Some(size)) => download_file_expecting_size(
+                    file_name,
+                    &destination,
+                    server_addr,
+                    size,
+                    &BusyRetry::default(),
+                    on_busy,
+                    report_progress,
+                ),
+                (None, None) => download_file_with_retry(
+                    file_name,
+                    &destination,
+                    server_addr,
+                    &BusyRetry::default(),
+                    on_busy,
+                    report_progress,
+                ),
+            };
+            emit_transfer_finished(file_name, "download", result.as_ref().map(|_| None), started, last_avg);
+            if result.as_ref().is_err_and(is_file_changed) {
+                let app_handle: &AppHandle = APP_HANDLE.get().expect("AppHandle not initialized");
+                app_handle.emit("file_changed", file_name).unwrap();
+            }
 
             
             match result {
@@ -56,9 +222,7 @@ async fn download_file_front(
                 Err(e) => Err(format!("Failed to download file '{}': {}", file_name, e)),
             }
         })
-        .unwrap_or_else(|| Err("Home directory not found".to_string()));
-
-    Ok("Download initiated".to_string())
+        .unwrap_or_else(|| Err("Home directory not found".to_string()))
 }
 
 #[derive(serde::Serialize)]
@@ -66,6 +230,7 @@ struct ProgressDataDownload {
     name: String,
     progress: f64,
     instant: f64,
+    instant_smoothed: f64,
     avg: f64,
     time: f64
 }
@@ -75,18 +240,137 @@ struct ProgressData {
     name: String,
     progress: f64,
     instant: f64,
+    instant_smoothed: f64,
     avg: f64,
 }
 
+/// Progress of a batch: `completed` files are done (either way) and `current`
+/// is `progress` percent through.
+#[derive(serde::Serialize)]
+struct BatchProgress {
+    completed: usize,
+    total: usize,
+    current: String,
+    progress: f64,
+    overall: f64,
+}
+
+#[derive(serde::Serialize)]
+struct BatchFailure {
+    name: String,
+    error: String,
+}
+
+#[derive(serde::Serialize)]
+struct BatchSummary {
+    succeeded: Vec<String>,
+    failed: Vec<BatchFailure>,
+}
+
+#[derive(serde::Serialize)]
+struct TransferStarted {
+    name: String,
+    direction: &'static str,
+    offset: u64,
+}
+
+#[derive(serde::Serialize)]
+struct ServerBusy {
+    name: String,
+    direction: &'static str,
+    delay_ms: u64,
+}
+
+#[derive(serde::Serialize)]
+struct TransferFinished {
+    name: String,
+    direction: &'static str,
+    success: bool,
+    error: Option<String>,
+    total_time: f64,
+    avg: f64,
+    /// CRC-32 in hex, when the server confirmed it in its upload ack.
+    checksum: Option<String>,
+}
+
+fn emit_transfer_started(name: &str, direction: &'static str, offset: u64) {
+    let app_handle: &AppHandle = APP_HANDLE.get().expect("AppHandle not initialized");
+    let event = if offset > 0 { "transfer_resumed" } else { "transfer_started" };
+    let payload = TransferStarted {
+        name: name.to_string(),
+        direction,
+        offset,
+    };
+    app_handle.emit(event, &payload).unwrap();
+}
+
+/// The server turned the transfer away for being at capacity and it is tried
+/// again after `delay`.
+fn emit_server_busy(name: &str, direction: &'static str, delay: Duration) {
+    let app_handle: &AppHandle = APP_HANDLE.get().expect("AppHandle not initialized");
+    let payload = ServerBusy {
+        name: name.to_string(),
+        direction,
+        delay_ms: delay.as_millis() as u64,
+    };
+    println!("Server busy, retrying {} of '{}' in {:.1}s", direction, name, delay.as_secs_f64());
+    app_handle.emit("server_busy", &payload).unwrap();
+}
+
+/// Every file weighs the same in `overall`, as sizes aren't known up front.
+fn emit_batch_progress(completed: usize, total: usize, current: &str, progress: f64) {
+    let app_handle: &AppHandle = APP_HANDLE.get().expect("AppHandle not initialized");
+    let payload = BatchProgress {
+        completed,
+        total,
+        current: current.to_string(),
+        progress,
+        overall: (completed as f64 + progress / 100.0) / total as f64 * 100.0,
+    };
+    app_handle.emit("batch_progress", &payload).unwrap();
+}
+
+/// `result` carries the verified checksum, if there is one.
+fn emit_transfer_finished(
+    name: &str,
+    direction: &'static str,
+    result: Result<Option<u32>, &std::io::Error>,
+    started: Instant,
+    avg: f64,
+) {
+    let app_handle: &AppHandle = APP_HANDLE.get().expect("AppHandle not initialized");
+    let payload = TransferFinished {
+        name: name.to_string(),
+        direction,
+        success: result.is_ok(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+        total_time: started.elapsed().as_secs_f64(),
+        avg,
+        checksum: result.ok().flatten().map(|crc| format!("{:08x}", crc)),
+    };
+    app_handle.emit("transfer_finished", &payload).unwrap();
+}
+
 #[tauri::command]
 async fn upload_file_front(
     server_ip: &str,
     server_port: &str,
     file_path: &str,
+    speed_smoothing: Option<f64>,
 ) -> Result<String, String> {
     let server_addr = format!("{}:{}", server_ip, server_port);
     let source = Path::new(file_path);
-    let result = upload_file(&source, &server_addr, |progress, instant, avg: f64| {
+    let upload_name = source
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("")
+            .to_string();
+    let started = Instant::now();
+    let mut last_avg = 0.0;
+    let mut smoother = SpeedSmoother::new(speed_smoothing.unwrap_or(DEFAULT_SPEED_SMOOTHING));
+    emit_transfer_started(&upload_name, "upload", 0);
+    let on_busy = |delay| emit_server_busy(&upload_name, "upload", delay);
+    let result = upload_file_with_retry(&source, &server_addr, &BusyRetry::default(), on_busy, |progress, instant, avg: f64| {
         let app_handle: &AppHandle = APP_HANDLE.get().expect("AppHandle not initialized");
         let file_name = source
                 .file_name()
@@ -97,12 +381,16 @@ async fn upload_file_front(
             name: file_name,
             progress,
             instant,
+            instant_smoothed: smoother.update(instant),
             avg,
         };
 
+        last_avg = avg;
+
         app_handle.emit("upload_progress", &response).unwrap();
         println!("Progress: {:6.2}% | Now: {:6.2} MB/s | Avg: {:6.2} MB/s | File: {}", progress, instant, avg, response.name);
     });
+    emit_transfer_finished(&upload_name, "upload", result.as_ref().map(|crc| *crc), started, last_avg);
 
     match result {
         Ok(_) => Ok(format!("File '{}' uploaded successfully from {:?}", file_path, source)),
@@ -124,7 +412,9 @@ pub fn run() {
         })
         .invoke_handler(tauri::generate_handler![
             get_available_files,
+            ping_server,
             download_file_front,
+            download_files_front,
             upload_file_front
         ])
         .run(tauri::generate_context!())
@@ -1,5 +1,13 @@
-use bevy::{prelude::*, window::{WindowResolution, PrimaryWindow}};
-use serde::Deserialize;
+use bevy::{
+    input::{
+        ButtonState,
+        keyboard::{Key, KeyboardInput},
+        mouse::{AccumulatedMouseMotion, AccumulatedMouseScroll, MouseScrollUnit},
+    },
+    prelude::*,
+    window::{WindowResolution, PrimaryWindow},
+};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 
 const WIDTH_WINDOW: u32 = 1200;
@@ -17,10 +25,47 @@ const MAX_LATITUDE: f32 = 90.0;
 
 const MAX_LENGTH_WIKIPEDIA_SUMMARY: usize = 80;
 
+const MIN_MAP_ZOOM: f32 = 1.0;
+const MAX_MAP_ZOOM: f32 = 8.0;
+const MAP_ZOOM_STEP: f32 = 1.1;
+
+const DEFAULT_MARKER_SPEED: f32 = 200.0;
+const MIN_MARKER_SPEED: f32 = 25.0;
+const MAX_MARKER_SPEED: f32 = 1600.0;
+const MARKER_SPEED_STEP: f32 = 1.25;
+const DEFAULT_SHIFT_MULTIPLIER: f32 = 0.5;
+const MIN_SHIFT_MULTIPLIER: f32 = 0.1;
+const MAX_SHIFT_MULTIPLIER: f32 = 4.0;
+const SHIFT_MULTIPLIER_STEP: f32 = 0.1;
+
+const MAX_GEOCODE_CANDIDATES: usize = 5;
+const MAX_SEARCH_QUERY_LENGTH: usize = 40;
+const CANDIDATE_KEYS: [KeyCode; MAX_GEOCODE_CANDIDATES] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+];
+
 fn get_weather_api_key() -> String {
     std::env::var("WEATHER_API_KEY").unwrap_or_else(|_| "YOUR_API_KEY".to_string())
 }
 
+fn get_wikipedia_language() -> String {
+    std::env::var("WIKIPEDIA_LANG")
+        .ok()
+        .map(|lang| lang.trim().to_lowercase())
+        .filter(|lang| !lang.is_empty() && lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        .unwrap_or_else(|| String::from("en"))
+}
+
+fn get_config_path() -> std::path::PathBuf {
+    std::env::var_os("WEATHER_APP_CONFIG")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| std::path::PathBuf::from("weather_app_config.json"))
+}
+
 fn main() {
     App::new()
     .add_plugins(
@@ -37,11 +82,16 @@ fn main() {
             .set(ImagePlugin::default_nearest())
     )
     .init_resource::<ApiChannel>()
+    .init_resource::<MapView>()
+    .insert_resource(MovementSettings::load(&get_config_path()))
+    .init_resource::<LocationSearch>()
+    .insert_non_send_resource(SystemClipboard::open())
     .add_systems(Startup, setup_app)
-    .add_systems(Update, scale_map_to_window)
-    .add_systems(Update, (update_man, update_coordinates_text).chain())
+    .add_systems(Update, (zoom_and_pan_map, adjust_movement_settings, update_man, scale_map_to_window, update_coordinates_text).chain())
     .add_systems(Update, check_space_key)
+    .add_systems(Update, copy_coordinates)
     .add_systems(Update, process_api_responses)
+    .add_systems(Update, (search_input, choose_search_candidate, update_search_text).chain())
     .run();
 }
 
@@ -52,9 +102,139 @@ struct Man {
     longitude: f32,
 }
 
+/// Zoom factor and pan offset of the visible map. `pan` is the map-space point
+/// (relative to the map center) shown in the middle of the view.
+#[derive(Resource)]
+struct MapView {
+    zoom: f32,
+    pan: Vec2,
+}
+
+impl Default for MapView {
+    fn default() -> Self {
+        Self {
+            zoom: MIN_MAP_ZOOM,
+            pan: Vec2::ZERO,
+        }
+    }
+}
+
+impl MapView {
+    fn clamp_pan(&mut self) {
+        let max_x = WIDTH_MAP / 2. * (1. - 1. / self.zoom);
+        let max_y = HEIGHT_MAP / 2. * (1. - 1. / self.zoom);
+        self.pan.x = self.pan.x.clamp(-max_x, max_x);
+        self.pan.y = self.pan.y.clamp(-max_y, max_y);
+    }
+}
+
+/// Marker speed in map units per second at the lowest zoom, and the factor
+/// applied while Shift is held. Saved to the config file whenever it changes.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct MovementSettings {
+    marker_speed: f32,
+    shift_multiplier: f32,
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        Self {
+            marker_speed: DEFAULT_MARKER_SPEED,
+            shift_multiplier: DEFAULT_SHIFT_MULTIPLIER,
+        }
+    }
+}
+
+impl MovementSettings {
+    /// A missing or unreadable config file gives the defaults.
+    fn load(path: &std::path::Path) -> Self {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(err) => {
+                println!("Failed to read config {}: {}", path.display(), err);
+                return Self::default();
+            }
+        };
+
+        match serde_json::from_str::<Self>(&contents) {
+            Ok(mut settings) => {
+                settings.clamp();
+                settings
+            }
+            Err(err) => {
+                println!("Ignoring malformed config {}: {}", path.display(), err);
+                Self::default()
+            }
+        }
+    }
+
+    fn save(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let contents = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, contents)
+    }
+
+    fn clamp(&mut self) {
+        if !self.marker_speed.is_finite() {
+            self.marker_speed = DEFAULT_MARKER_SPEED;
+        }
+        if !self.shift_multiplier.is_finite() {
+            self.shift_multiplier = DEFAULT_SHIFT_MULTIPLIER;
+        }
+        self.marker_speed = self.marker_speed.clamp(MIN_MARKER_SPEED, MAX_MARKER_SPEED);
+        self.shift_multiplier = self.shift_multiplier.clamp(MIN_SHIFT_MULTIPLIER, MAX_SHIFT_MULTIPLIER);
+    }
+}
+
+/// Where the forward search by place name currently is.
+#[derive(Default)]
+enum SearchPhase {
+    #[default]
+    Idle,
+    Typing,
+    Searching,
+    Choosing(Vec<GeocodeCandidate>),
+    NotFound,
+}
+
+/// Forward search by place name. While typing, key presses edit `query`
+/// instead of moving the marker or fetching weather.
+#[derive(Resource, Default)]
+struct LocationSearch {
+    query: String,
+    phase: SearchPhase,
+}
+
+impl LocationSearch {
+    fn typing(&self) -> bool {
+        matches!(self.phase, SearchPhase::Typing)
+    }
+}
+
+/// The system clipboard, opened once at startup and kept open: on X11 and
+/// Wayland copied text is only served while the `Clipboard` that set it is
+/// alive. `None` when there is no clipboard to open.
+struct SystemClipboard(Option<arboard::Clipboard>);
+
+impl SystemClipboard {
+    fn open() -> Self {
+        match arboard::Clipboard::new() {
+            Ok(clipboard) => Self(Some(clipboard)),
+            Err(err) => {
+                println!("Clipboard unavailable: {}", err);
+                Self(None)
+            }
+        }
+    }
+}
+
 #[derive(Component)]
 struct CoordinatesText;
 
+#[derive(Component)]
+struct SearchText;
+
 #[derive(Component)]
 struct WeatherText;
 
@@ -95,10 +275,31 @@ struct GeocodeResponse {
     name: String,
     country: String,
     state: Option<String>,
+    lat: f32,
+    lon: f32,
+}
+
+impl GeocodeResponse {
+    fn display_name(&self) -> String {
+        match &self.state {
+            Some(state) => format!("{}, {}, {}", self.name, state, self.country),
+            None => format!("{}, {}", self.name, self.country),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct GeocodeCandidate {
+    display_name: String,
+    latitude: f32,
+    longitude: f32,
 }
 
 #[derive(Debug, Clone)]
 struct WeatherData {
+    latitude: f32,
+    longitude: f32,
+    /// City, region and country are empty unless `location_known`.
     city: String,
     region: String,
     country: String,
@@ -106,6 +307,7 @@ struct WeatherData {
     wind_speed: f64,
     weather: String,
     description: String,
+    location_known: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -115,6 +317,10 @@ enum ApiUpdate {
         location: String,
         summary: Option<String>,
     },
+    SearchResults {
+        query: String,
+        candidates: Result<Vec<GeocodeCandidate>, String>,
+    },
 }
 
 #[derive(Resource)]
@@ -155,7 +361,7 @@ fn setup_app(
         },
         Transform::from_xyz(0., 0., 0.).with_scale(Vec3::splat(PIXEL_RATIO)),
         Man { 
-            position: Vec2::new(0., 0.),
+            position: Vec2::new(WIDTH_MAP / 2., HEIGHT_MAP / 2.),
             latitude: 0.0,
             longitude: 0.0,
         }
@@ -183,7 +389,7 @@ fn setup_app(
             }
         ).with_children(|parent| {
             parent.spawn((
-                Text::new("X: 0.0\nY: 0.0\n\nlat:  0.0\nlong: 0.0"),
+                Text::new("X: 0.0\nY: 0.0\n\nlat:  0.0\nlong: 0.0\n\nspeed: 200 (shift x0.50)"),
                 TextFont {
                     font: ui_font.clone(),
                     font_size: 18.0,
@@ -202,7 +408,7 @@ fn setup_app(
             }
         ).with_children(|parent| {
             parent.spawn((
-                Text::new("Press SPACE\nto fetch weather"),
+                Text::new("Press SPACE\nto fetch weather\nC to copy coordinates\n+/- speed, [/] shift speed"),
                 TextFont {
                     font: ui_font.clone(),
                     font_size: 16.0,
@@ -213,6 +419,26 @@ fn setup_app(
             ));
         }).insert(BackgroundColor(Color::srgba(0.1, 0.1, 0.15, 0.8)));
 
+        parent.spawn(
+            Node {
+                width: Val::Percent(100.0),
+                padding: UiRect::all(Val::Px(15.0)),
+                margin: UiRect::bottom(Val::Px(10.0)),
+                ..Default::default()
+            }
+        ).with_children(|parent| {
+            parent.spawn((
+                Text::new("ENTER to search a place"),
+                TextFont {
+                    font: ui_font.clone(),
+                    font_size: 16.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                SearchText,
+            ));
+        }).insert(BackgroundColor(Color::srgba(0.15, 0.1, 0.1, 0.8)));
+
         parent.spawn(
             Node {
                 width: Val::Percent(100.0),
@@ -252,18 +478,29 @@ async fn fetch_weather_data(client: &reqwest::Client, lat: f32, lon: f32) -> Res
     );
     
     let weather = weather_result?;
-    let geocode = geocode_result?;
 
-    let region = geocode.state.unwrap_or_else(|| String::from("-"));
+    let (city, region, country, location_known) = match geocode_result {
+        Ok(geocode) => {
+            let region = geocode.state.unwrap_or_else(|| String::from("-"));
+            (geocode.name, region, geocode.country, true)
+        }
+        Err(err) => {
+            println!("Reverse geocoding failed for ({:.4}, {:.4}): {}", lat, lon, err);
+            (String::new(), String::new(), String::new(), false)
+        }
+    };
     
     Ok(WeatherData {
-        city: geocode.name,
+        latitude: lat,
+        longitude: lon,
+        city,
         region,
-        country: geocode.country,
+        country,
         temperature: weather.main.temp - 273.15,
         wind_speed: weather.wind.speed,
         weather: weather.weather[0].main.clone(),
         description: weather.weather[0].description.clone(),
+        location_known,
     })
 }
 
@@ -290,12 +527,67 @@ async fn fetch_geocode(client: &reqwest::Client, lat: f32, lon: f32) -> Result<G
     geocode_list.pop().ok_or_else(|| "No location found".into())
 }
 
+async fn fetch_geocode_candidates(client: &reqwest::Client, query: &str) -> Result<Vec<GeocodeCandidate>, Box<dyn std::error::Error + Send + Sync>> {
+    let limit = MAX_GEOCODE_CANDIDATES.to_string();
+    let api_key = get_weather_api_key();
+    let response = client
+        .get("https://api.openweathermap.org/geo/1.0/direct")
+        .query(&[("q", query), ("limit", limit.as_str()), ("appid", api_key.as_str())])
+        .send()
+        .await?;
+    let geocode_list = response.json::<Vec<GeocodeResponse>>().await?;
+
+    Ok(geocode_list
+        .into_iter()
+        .map(|geocode| GeocodeCandidate {
+            display_name: geocode.display_name(),
+            latitude: geocode.lat,
+            longitude: geocode.lon,
+        })
+        .collect())
+}
+
+async fn fetch_wikipedia_with_fallback(
+    client: &reqwest::Client,
+    data: &WeatherData,
+) -> Result<(String, Option<String>), Box<dyn std::error::Error + Send + Sync>> {
+    let preferred = get_wikipedia_language();
+    let mut languages = vec![preferred.as_str()];
+    if preferred != "en" {
+        languages.push("en");
+    }
+
+    let mut last_error = None;
+    for title in [&data.region, &data.city] {
+        if title.is_empty() || title == "-" {
+            continue;
+        }
+
+        for lang in &languages {
+            match fetch_wikipedia_description(client, lang, title).await {
+                Ok(Some(summary)) => return Ok((title.clone(), Some(summary))),
+                Ok(None) => println!("No {} Wikipedia summary for {}", lang, title),
+                Err(err) => {
+                    println!("{} Wikipedia request failed for {}: {}", lang, title, err);
+                    last_error = Some(err);
+                }
+            }
+        }
+    }
+
+    match last_error {
+        Some(err) => Err(err),
+        None => Ok((data.region.clone(), None)),
+    }
+}
+
 async fn fetch_wikipedia_description(
     client: &reqwest::Client,
+    lang: &str,
     title: &str,
 ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
     let response = client
-        .get("https://en.wikipedia.org/w/api.php")
+        .get(format!("https://{}.wikipedia.org/w/api.php", lang))
         .query(&[
             ("action", "query"),
             ("format", "json"),
@@ -332,6 +624,35 @@ async fn fetch_wikipedia_description(
     Ok(None)
 }
 
+/// Shortens `text` to at most `max_chars` characters, ellipsis included.
+/// The cut is moved back to the last space so no word is split; text without
+/// spaces in the kept part (CJK, long emoji runs) is cut between characters.
+/// Those are `char`s, not graphemes: a ZWJ emoji sequence such as 👨‍👩‍👧 or a
+/// letter with combining marks may lose its tail at the cut.
+fn truncate_summary(text: &str, max_chars: usize) -> String {
+    const ELLIPSIS: &str = "...";
+    let ellipsis_chars = ELLIPSIS.chars().count();
+
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let budget = max_chars.saturating_sub(ellipsis_chars);
+    if budget == 0 {
+        return ELLIPSIS.chars().take(max_chars).collect();
+    }
+
+    let cut = text.char_indices().nth(budget).map_or(text.len(), |(index, _)| index);
+    let mut kept = &text[..cut];
+    let splits_word = !text[cut..].starts_with(char::is_whitespace);
+    let last_space = kept.rfind(char::is_whitespace).filter(|&space| !kept[..space].trim_end().is_empty());
+    if let (true, Some(space)) = (splits_word, last_space) {
+        kept = &kept[..space];
+    }
+    // A sentence ending right at the cut would otherwise get four dots.
+    let kept = kept.trim_end().trim_end_matches(['.', '\u{2026}']);
+    format!("{}{}", kept, ELLIPSIS)
+}
+
 fn check_space_key(
     keys: Res<ButtonInput<KeyCode>>,
     man_query: Query<&Man>,
@@ -339,7 +660,12 @@ fn check_space_key(
     mut status_query: Query<&mut Text, With<StatusText>>,
     mut weather_query: Query<&mut Text, (With<WeatherText>, Without<StatusText>, Without<WikipediaText>)>,
     mut wiki_query: Query<&mut Text, (With<WikipediaText>, Without<StatusText>, Without<WeatherText>)>,
+    search: Res<LocationSearch>,
 ) {
+    if search.typing() {
+        return;
+    }
+
     if keys.just_pressed(KeyCode::Space) {
         if let Ok(man) = man_query.single() {
             let lat = man.latitude;
@@ -378,10 +704,15 @@ fn check_space_key(
                     let weather_data = fetch_weather_data(&client, lat, lon).await?;
 
                     let region = weather_data.region.clone();
-                    let _ = sender_weather.send(Ok(ApiUpdate::Weather(weather_data)));
+                    let location_known = weather_data.location_known;
+                    let _ = sender_weather.send(Ok(ApiUpdate::Weather(weather_data.clone())));
+                    if !location_known {
+                        return Ok(());
+                    }
+                    let wiki_result = fetch_wikipedia_with_fallback(&client, &weather_data).await;
 
-                    match fetch_wikipedia_description(&client, &region).await {
-                        Ok(summary) => {
+                    match wiki_result {
+                        Ok((region, summary)) => {
                             let Some(text) = &summary else {
                                 println!("No Wikipedia summary found for {}", region);
                                 let _ = sender_wiki.send(Ok(ApiUpdate::Wikipedia {
@@ -391,13 +722,7 @@ fn check_space_key(
                                 return Ok(());
                             };
 
-                            let final_text = if text.chars().count() > MAX_LENGTH_WIKIPEDIA_SUMMARY {
-                                let truncated: String = text.chars().take(MAX_LENGTH_WIKIPEDIA_SUMMARY - 3).collect();
-                                format!("{}...", truncated)
-                            } else {
-                                text.clone()
-                            };
-                            
+                            let final_text = truncate_summary(text, MAX_LENGTH_WIKIPEDIA_SUMMARY);
 
                             let _ = sender_wiki.send(Ok(ApiUpdate::Wikipedia {
                                 location: region,
@@ -427,30 +752,91 @@ fn check_space_key(
     }
 }
 
+fn copy_coordinates(
+    keys: Res<ButtonInput<KeyCode>>,
+    man_query: Query<&Man>,
+    mut status_query: Query<&mut Text, With<StatusText>>,
+    search: Res<LocationSearch>,
+    mut clipboard: NonSendMut<SystemClipboard>,
+) {
+    if !keys.just_pressed(KeyCode::KeyC) || search.typing() {
+        return;
+    }
+    let Ok(man) = man_query.single() else {
+        return;
+    };
+
+    let text = format!(
+        "{:.6}, {:.6}\nhttps://www.google.com/maps?q={:.6},{:.6}\nhttps://openweathermap.org/weathermap?lat={:.6}&lon={:.6}&zoom=8",
+        man.latitude,
+        man.longitude,
+        man.latitude,
+        man.longitude,
+        man.latitude,
+        man.longitude,
+    );
+
+    let status = match clipboard.0.as_mut().map(|clipboard| clipboard.set_text(text)) {
+        Some(Ok(())) => format!("Copied {:.4}, {:.4}\nPress SPACE", man.latitude, man.longitude),
+        Some(Err(err)) => {
+            println!("Failed to copy coordinates: {}", err);
+            "Copy failed\nPress SPACE".to_string()
+        }
+        None => "Clipboard unavailable\nPress SPACE".to_string(),
+    };
+
+    if let Ok(mut status_text) = status_query.single_mut() {
+        **status_text = status;
+    }
+}
+
 fn process_api_responses(
     mut api_channel: ResMut<ApiChannel>,
     mut weather_query: Query<&mut Text, (With<WeatherText>, Without<StatusText>, Without<WikipediaText>)>,
     mut wiki_query: Query<&mut Text, (With<WikipediaText>, Without<StatusText>, Without<WeatherText>)>,
     mut status_query: Query<&mut Text, With<StatusText>>,
+    mut search: ResMut<LocationSearch>,
 ) {
     while let Ok(result) = api_channel.receiver.try_recv() {
         match result {
             Ok(ApiUpdate::Weather(data)) => {
                 if let Ok(mut weather_text) = weather_query.single_mut() {
-                    **weather_text = format!(
-                        "{},\n{},\n{}\n\n{}\n{}\n{:.1}\n{:.1} m/s",
-                        data.country,
-                        data.region,
-                        data.city,
-                        data.weather,
-                        data.description,
-                        data.temperature,
-                        data.wind_speed,
-                    );
+                    if data.location_known {
+                        **weather_text = format!(
+                            "{},\n{},\n{}\n\n{}\n{}\n{:.1}\n{:.1} m/s",
+                            data.country,
+                            data.region,
+                            data.city,
+                            data.weather,
+                            data.description,
+                            data.temperature,
+                            data.wind_speed,
+                        );
+                    } else {
+                        **weather_text = format!(
+                            "{:.4}, {:.4}\n(location name unavailable)\n\n{}\n{}\n{:.1}\n{:.1} m/s",
+                            data.latitude,
+                            data.longitude,
+                            data.weather,
+                            data.description,
+                            data.temperature,
+                            data.wind_speed,
+                        );
+                    }
+                }
+
+                if !data.location_known
+                    && let Ok(mut wiki_text) = wiki_query.single_mut()
+                {
+                    **wiki_text = "Wikipedia: location name unavailable".to_string();
                 }
 
                 if let Ok(mut status_text) = status_query.single_mut() {
-                    **status_text = "Weather loaded\nFetching Wikipedia...".to_string();
+                    **status_text = if data.location_known {
+                        "Weather loaded\nFetching Wikipedia...".to_string()
+                    } else {
+                        "Loaded!\nPress SPACE".to_string()
+                    };
                 }
             }
             Ok(ApiUpdate::Wikipedia { location, summary }) => {
@@ -476,6 +862,22 @@ fn process_api_responses(
                     **status_text = "Loaded!\nPress SPACE".to_string();
                 }
             }
+            Ok(ApiUpdate::SearchResults { query, candidates }) => {
+                // A newer search or a dismissal supersedes this answer.
+                if !matches!(search.phase, SearchPhase::Searching) || search.query != query {
+                    continue;
+                }
+                match candidates {
+                    Ok(candidates) if candidates.is_empty() => search.phase = SearchPhase::NotFound,
+                    Ok(candidates) => search.phase = SearchPhase::Choosing(candidates),
+                    Err(err_msg) => {
+                        search.phase = SearchPhase::Idle;
+                        if let Ok(mut status_text) = status_query.single_mut() {
+                            **status_text = format!("Search failed: {}\nPress SPACE", err_msg);
+                        }
+                    }
+                }
+            }
             Err(err_msg) => {
                 if let Ok(mut status_text) = status_query.single_mut() {
                     **status_text = format!("Error: {}\nPress SPACE", err_msg);
@@ -493,11 +895,226 @@ fn process_api_responses(
     }
 }
 
+/// ENTER starts typing a place name and ENTER again looks it up; ESC cancels
+/// typing or dismisses the results.
+fn search_input(
+    mut keyboard_events: MessageReader<KeyboardInput>,
+    mut search: ResMut<LocationSearch>,
+    api_channel: Res<ApiChannel>,
+) {
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+
+        if !search.typing() {
+            match event.logical_key {
+                Key::Enter if !event.repeat => search.phase = SearchPhase::Typing,
+                Key::Escape => search.phase = SearchPhase::Idle,
+                _ => {}
+            }
+            continue;
+        }
+
+        match &event.logical_key {
+            Key::Enter => {
+                let query = search.query.trim().to_string();
+                if query.is_empty() {
+                    search.phase = SearchPhase::Idle;
+                    continue;
+                }
+                search.query = query.clone();
+                search.phase = SearchPhase::Searching;
+                spawn_location_search(query, api_channel.sender.clone());
+            }
+            Key::Escape => search.phase = SearchPhase::Idle,
+            Key::Backspace => {
+                search.query.pop();
+            }
+            _ => {
+                if let Some(text) = &event.text {
+                    for c in text.chars().filter(|c| !c.is_control()) {
+                        if search.query.chars().count() < MAX_SEARCH_QUERY_LENGTH {
+                            search.query.push(c);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn spawn_location_search(query: String, sender: mpsc::UnboundedSender<Result<ApiUpdate, String>>) {
+    println!("Searching for places named {:?}", query);
+    std::thread::spawn(move || {
+        let rt = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(rt) => rt,
+            Err(e) => {
+                println!("Failed to build runtime: {}", e);
+                return;
+            }
+        };
+
+        let candidates = rt.block_on(async {
+            let client = reqwest::Client::new();
+            fetch_geocode_candidates(&client, &query).await
+        });
+        if let Err(err) = &candidates {
+            println!("Place search failed for {:?}: {}", query, err);
+        }
+
+        let _ = sender.send(Ok(ApiUpdate::SearchResults {
+            query,
+            candidates: candidates.map_err(|err| err.to_string()),
+        }));
+    });
+}
+
+/// Number keys pick one of the listed places; the marker jumps there and the
+/// view is centered on it.
+fn choose_search_candidate(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut search: ResMut<LocationSearch>,
+    mut man_query: Query<&mut Man>,
+    mut view: ResMut<MapView>,
+    mut status_query: Query<&mut Text, With<StatusText>>,
+) {
+    let SearchPhase::Choosing(candidates) = &search.phase else {
+        return;
+    };
+    let Some(candidate) = CANDIDATE_KEYS
+        .iter()
+        .zip(candidates)
+        .find(|(key, _)| keys.just_pressed(**key))
+        .map(|(_, candidate)| candidate.clone())
+    else {
+        return;
+    };
+    let Ok(mut man) = man_query.single_mut() else {
+        return;
+    };
+
+    let x = (candidate.longitude - MIN_LONGITUDE) / (MAX_LONGITUDE - MIN_LONGITUDE) * WIDTH_MAP;
+    let y = (candidate.latitude - MIN_LATITUDE) / (MAX_LATITUDE - MIN_LATITUDE) * HEIGHT_MAP;
+    man.position = Vec2::new(x.clamp(0., WIDTH_MAP), y.clamp(0., HEIGHT_MAP));
+    view.pan = man.position - Vec2::new(WIDTH_MAP / 2., HEIGHT_MAP / 2.);
+    view.clamp_pan();
+
+    println!("Moved to {} ({:.4}, {:.4})", candidate.display_name, candidate.latitude, candidate.longitude);
+    if let Ok(mut status_text) = status_query.single_mut() {
+        **status_text = format!("Moved to {}\nPress SPACE", candidate.display_name);
+    }
+    search.phase = SearchPhase::Idle;
+}
+
+fn update_search_text(
+    search: Res<LocationSearch>,
+    mut text_query: Query<&mut Text, With<SearchText>>,
+) {
+    if !search.is_changed() {
+        return;
+    }
+    let Ok(mut text) = text_query.single_mut() else {
+        return;
+    };
+
+    **text = match &search.phase {
+        SearchPhase::Idle => "ENTER to search a place".to_string(),
+        SearchPhase::Typing => format!("Search: {}_\nENTER to search, ESC to cancel", search.query),
+        SearchPhase::Searching => format!("Searching for \"{}\"...", search.query),
+        SearchPhase::Choosing(candidates) => {
+            let mut listing = format!("Places named \"{}\":\n", search.query);
+            for (index, candidate) in candidates.iter().enumerate() {
+                listing.push_str(&format!("{}. {}\n", index + 1, candidate.display_name));
+            }
+            listing.push_str(&format!("1-{} to choose, ESC to dismiss", candidates.len()));
+            listing
+        }
+        SearchPhase::NotFound => format!("No places found for \"{}\"\nENTER to search again", search.query),
+    };
+}
+
+fn base_map_scale(window: &Window) -> f32 {
+    let available_width = window.width() - UI_PANEL_WIDTH;
+    let available_height = window.height();
+    let scale_x = available_width / WIDTH_MAP;
+    let scale_y = available_height / HEIGHT_MAP;
+    scale_x.min(scale_y) * 0.9
+}
+
+fn zoom_and_pan_map(
+    mut view: ResMut<MapView>,
+    scroll: Res<AccumulatedMouseScroll>,
+    motion: Res<AccumulatedMouseMotion>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+) {
+    let Ok(window) = window_query.single() else {
+        return;
+    };
+
+    if scroll.delta.y != 0.0 {
+        let step = if scroll.unit == MouseScrollUnit::Line { scroll.delta.y } else { scroll.delta.y / 100.0 };
+        view.zoom = (view.zoom * MAP_ZOOM_STEP.powf(step)).clamp(MIN_MAP_ZOOM, MAX_MAP_ZOOM);
+    }
+
+    if buttons.pressed(MouseButton::Middle) && motion.delta != Vec2::ZERO {
+        let scale = base_map_scale(window) * view.zoom;
+        view.pan.x -= motion.delta.x / scale;
+        view.pan.y += motion.delta.y / scale;
+    }
+
+    view.clamp_pan();
+}
+
+/// +/- change the marker speed and [/] the Shift multiplier; the result is
+/// written to the config file right away.
+fn adjust_movement_settings(
+    keys: Res<ButtonInput<KeyCode>>,
+    search: Res<LocationSearch>,
+    mut settings: ResMut<MovementSettings>,
+) {
+    if search.typing() {
+        return;
+    }
+
+    let mut changed = settings.clone();
+    if keys.any_just_pressed([KeyCode::Equal, KeyCode::NumpadAdd]) {
+        changed.marker_speed *= MARKER_SPEED_STEP;
+    }
+    if keys.any_just_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        changed.marker_speed /= MARKER_SPEED_STEP;
+    }
+    if keys.just_pressed(KeyCode::BracketRight) {
+        changed.shift_multiplier += SHIFT_MULTIPLIER_STEP;
+    }
+    if keys.just_pressed(KeyCode::BracketLeft) {
+        changed.shift_multiplier -= SHIFT_MULTIPLIER_STEP;
+    }
+    changed.clamp();
+
+    if changed.marker_speed == settings.marker_speed && changed.shift_multiplier == settings.shift_multiplier {
+        return;
+    }
+
+    let path = get_config_path();
+    if let Err(err) = changed.save(&path) {
+        println!("Failed to save config {}: {}", path.display(), err);
+    }
+    *settings = changed;
+}
+
 fn update_man(
     mut man_query: Query<(&mut Transform, &mut Man)>,
     window_query: Query<&Window, With<PrimaryWindow>>,
+    mut view: ResMut<MapView>,
     time: Res<Time>,
-    keys: Res<ButtonInput<KeyCode>>
+    keys: Res<ButtonInput<KeyCode>>,
+    search: Res<LocationSearch>,
+    settings: Res<MovementSettings>,
 ) {
     let Ok((mut transform, mut man)) = man_query.single_mut() else {
         return;
@@ -508,75 +1125,74 @@ fn update_man(
     
     let mut direction = Vec2::ZERO;
 
-    if keys.pressed(KeyCode::KeyW) {
-        direction.y += 1.0;
-    }
-    if keys.pressed(KeyCode::KeyS) {
-        direction.y -= 1.0;
-    }
-    if keys.pressed(KeyCode::KeyA) {
-        direction.x -= 1.0;
-    }
-    if keys.pressed(KeyCode::KeyD) {
-        direction.x += 1.0;
+    if !search.typing() {
+        if keys.pressed(KeyCode::KeyW) {
+            direction.y += 1.0;
+        }
+        if keys.pressed(KeyCode::KeyS) {
+            direction.y -= 1.0;
+        }
+        if keys.pressed(KeyCode::KeyA) {
+            direction.x -= 1.0;
+        }
+        if keys.pressed(KeyCode::KeyD) {
+            direction.x += 1.0;
+        }
     }
 
     if direction != Vec2::ZERO {
         direction = direction.normalize();
     }
 
-    let base_speed = 200.0;
-    
     let speed = if keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight) {
-        base_speed * 0.5
+        settings.marker_speed * settings.shift_multiplier
     } else {
-        base_speed
+        settings.marker_speed
     };
 
-    transform.translation += direction.extend(0.0) * speed * time.delta_secs();
-    
-    let available_width = window.width() - UI_PANEL_WIDTH;
-    let available_height = window.height();
-    let scale_x = available_width / WIDTH_MAP;
-    let scale_y = available_height / HEIGHT_MAP;
-    let scale = scale_x.min(scale_y) * 0.9;
-    
-    let scaled_width = WIDTH_MAP * scale;
-    let scaled_height = HEIGHT_MAP * scale;
-    let offset_x = -(UI_PANEL_WIDTH / 2.0);
+    let scale = base_map_scale(window) * view.zoom;
 
-    transform.translation.x = transform.translation.x.clamp(
-        offset_x - scaled_width / 2.,
-        offset_x + scaled_width / 2.
-    );
-    transform.translation.y = transform.translation.y.clamp(
-        -scaled_height / 2.,
-        scaled_height / 2.
-    );
-    
-    let map_x = (transform.translation.x - offset_x) / scale + WIDTH_MAP / 2.;
-    let map_y = transform.translation.y / scale + HEIGHT_MAP / 2.;
+    // Speed is in map units, so crossing the map takes as long on any window
+    // size, and it shrinks with zoom so the marker keeps the same pace on screen
+    // relative to the visible part of the map.
+    let mut map_position = man.position + direction * speed * time.delta_secs() / view.zoom;
+    map_position.x = map_position.x.clamp(0., WIDTH_MAP);
+    map_position.y = map_position.y.clamp(0., HEIGHT_MAP);
+
+    if direction != Vec2::ZERO {
+        let half_view = Vec2::new(WIDTH_MAP, HEIGHT_MAP) / (2. * view.zoom);
+        let relative = map_position - Vec2::new(WIDTH_MAP / 2., HEIGHT_MAP / 2.);
+        view.pan = view.pan.clamp(relative - half_view, relative + half_view);
+        view.clamp_pan();
+    }
 
-    let longitude = MIN_LONGITUDE + (map_x / WIDTH_MAP) * (MAX_LONGITUDE - MIN_LONGITUDE);
-    let latitude = MIN_LATITUDE + (map_y / HEIGHT_MAP) * (MAX_LATITUDE - MIN_LATITUDE);
+    let offset_x = -(UI_PANEL_WIDTH / 2.0);
+    transform.translation.x = offset_x + (map_position.x - WIDTH_MAP / 2. - view.pan.x) * scale;
+    transform.translation.y = (map_position.y - HEIGHT_MAP / 2. - view.pan.y) * scale;
+
+    let longitude = MIN_LONGITUDE + (map_position.x / WIDTH_MAP) * (MAX_LONGITUDE - MIN_LONGITUDE);
+    let latitude = MIN_LATITUDE + (map_position.y / HEIGHT_MAP) * (MAX_LATITUDE - MIN_LATITUDE);
     
-    man.position = Vec2::new(map_x, map_y);
+    man.position = map_position;
     man.longitude = longitude;
     man.latitude = latitude;
 }
 
 fn update_coordinates_text(
     man_query: Query<&Man>,
+    settings: Res<MovementSettings>,
     mut text_query: Query<&mut Text, With<CoordinatesText>>
 ) {
     if let Ok(man) = man_query.single() {
         if let Ok(mut text) = text_query.single_mut() {
             **text = format!(
-                "X: {:.3}\nY: {:.3}\n\nlat:  {:.6}\nlong: {:.6}",
+                "X: {:.3}\nY: {:.3}\n\nlat:  {:.6}\nlong: {:.6}\n\nspeed: {:.0} (shift x{:.2})",
                 man.position.x,
                 man.position.y,
                 man.latitude,
-                man.longitude
+                man.longitude,
+                settings.marker_speed,
+                settings.shift_multiplier
             );
         }
     }
@@ -584,6 +1200,7 @@ fn update_coordinates_text(
 
 fn scale_map_to_window(
     window_query: Query<&Window, With<PrimaryWindow>>,
+    view: Res<MapView>,
     mut map_query: Query<(&mut Transform, &mut Sprite), With<GameMap>>,
 ) {
     let Ok(window) = window_query.single() else {
@@ -593,15 +1210,52 @@ fn scale_map_to_window(
         return;
     };
     
-    let available_width = window.width() - UI_PANEL_WIDTH;
-    let available_height = window.height();
-    
-    let scale_x = available_width / WIDTH_MAP;
-    let scale_y = available_height / HEIGHT_MAP;
-    let scale = scale_x.min(scale_y) * 0.9;
+    let scale = base_map_scale(window) * view.zoom;
     
     map_sprite.custom_size = Some(Vec2::new(WIDTH_MAP * scale, HEIGHT_MAP * scale));
 
     let offset_x = -(UI_PANEL_WIDTH / 2.0);
-    map_transform.translation.x = offset_x;
-}
\ No newline at end of file
+    map_transform.translation.x = offset_x - view.pan.x * scale;
+    map_transform.translation.y = -view.pan.y * scale;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_summary_backs_up_to_a_word_boundary() {
+        assert_eq!(truncate_summary("Hello wonderful world", 10), "Hello...");
+        assert_eq!(truncate_summary("Supercalifragilistic", 10), "Superca...");
+    }
+
+    #[test]
+    fn truncate_summary_keeps_a_word_ending_at_the_cut() {
+        assert_eq!(truncate_summary("Hello world again", 14), "Hello world...");
+        assert_eq!(truncate_summary("Hi there. More text", 12), "Hi there...");
+    }
+
+    #[test]
+    fn truncate_summary_cuts_cjk_between_characters() {
+        let text = "東京都は日本の首都である";
+        assert_eq!(truncate_summary(text, 8), "東京都は日...");
+        assert_eq!(truncate_summary(text, 12), text);
+    }
+
+    #[test]
+    fn truncate_summary_may_split_zwj_sequences() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let text = family.repeat(2);
+        assert_eq!(truncate_summary(&text, 6), "\u{1F468}\u{200D}\u{1F469}...");
+        assert_eq!(truncate_summary(&text, 8), format!("{family}..."));
+    }
+
+    #[test]
+    fn truncate_summary_handles_limits_below_the_ellipsis() {
+        let text = "Hello world";
+        assert_eq!(truncate_summary(text, 0), "");
+        assert_eq!(truncate_summary(text, 1), ".");
+        assert_eq!(truncate_summary(text, 2), "..");
+        assert_eq!(truncate_summary(text, 3), "...");
+    }
+}
@@ -1,8 +1,10 @@
 use multicast::*;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread;
-use tauri::{Emitter, State};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tauri::{Emitter, Manager, State};
 use serde::{Deserialize, Serialize};
 use socket2::SockAddr;
 
@@ -17,8 +19,53 @@ struct MessageEvent {
 struct AppState {
     server_running: Arc<AtomicBool>,
     client_running: Arc<AtomicBool>,
+    client_paused: Arc<AtomicBool>,
     instance_id: Mutex<Option<String>>,
     config: Mutex<Option<MulticastConfig>>,
+    server_thread: Mutex<Option<JoinHandle<()>>>,
+    client_thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+const THREAD_JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+const MULTICAST_JOIN_TIMEOUT: Duration = Duration::from_secs(3);
+const DEVICE_QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const DEVICE_ROSTER_FILE: &str = "device_roster.txt";
+
+/// Where the device list is kept while the server is stopped, so it shows up
+/// again (as stale) right after a restart.
+fn device_roster_path(app: &tauri::AppHandle) -> Option<PathBuf> {
+    let dir = app.path().app_data_dir().ok()?;
+    std::fs::create_dir_all(&dir).ok()?;
+    Some(dir.join(DEVICE_ROSTER_FILE))
+}
+
+fn join_with_timeout(handle: JoinHandle<()>, timeout: Duration) -> Result<(), JoinHandle<()>> {
+    let deadline = Instant::now() + timeout;
+    while !handle.is_finished() {
+        if Instant::now() >= deadline {
+            return Err(handle);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let _ = handle.join();
+    Ok(())
+}
+
+fn thread_alive(slot: &Mutex<Option<JoinHandle<()>>>) -> bool {
+    slot.lock().unwrap().as_ref().is_some_and(|handle| !handle.is_finished())
+}
+
+/// Clears `flag` and waits for the thread in `slot`. A thread that doesn't exit
+/// in time stays in the slot, so starting that half again is refused until it has.
+fn stop_thread(flag: &AtomicBool, slot: &Mutex<Option<JoinHandle<()>>>, name: &str) {
+    flag.store(false, Ordering::Relaxed);
+    let mut slot = slot.lock().unwrap();
+    if let Some(handle) = slot.take() {
+        if let Err(handle) = join_with_timeout(handle, THREAD_JOIN_TIMEOUT) {
+            log::warn!("[STOP] {} thread did not exit within {:?}", name, THREAD_JOIN_TIMEOUT);
+            *slot = Some(handle);
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -27,6 +74,17 @@ struct StartConfig {
     port: u16,
     message: String,
     interface: Option<String>,
+    min_interval_ms: Option<u64>,
+    heartbeat_jitter: Option<f64>,
+    instance_id: Option<String>,
+    compress_payloads: Option<bool>,
+    device_timeout_ms: Option<u64>,
+    preferred_interfaces: Option<Vec<String>>,
+    prefer_physical_interfaces: Option<bool>,
+    send_on_all_interfaces: Option<bool>,
+    max_message_size: Option<usize>,
+    /// Group of the other address family to announce and listen on as well.
+    dual_stack_ip: Option<String>,
 }
 
 #[tauri::command]
@@ -35,10 +93,14 @@ fn start_multicast(
     state: State<AppState>,
     app: tauri::AppHandle,
 ) -> Result<String, String> {
-    if state.server_running.load(Ordering::Relaxed) {
+    if state.config.lock().unwrap().is_some() {
         return Err("Multicast already running".to_string());
     }
 
+    if thread_alive(&state.server_thread) || thread_alive(&state.client_thread) {
+        return Err("Previous session is still shutting down".to_string());
+    }
+
     let mcast_config = MulticastConfig::from_ip_string_with_interface(
         &config.ip,
         config.port,
@@ -46,100 +108,161 @@ fn start_multicast(
         config.interface,
     )
     .map_err(|e| e.to_string())?;
+    let mcast_config = match config.min_interval_ms {
+        Some(ms) => mcast_config.with_min_message_interval(Duration::from_millis(ms)),
+        None => mcast_config,
+    };
+    let mcast_config = match config.heartbeat_jitter {
+        Some(jitter) => mcast_config.with_heartbeat_jitter(jitter),
+        None => mcast_config,
+    };
+    let mcast_config = match config.instance_id.as_deref() {
+        Some(id) => mcast_config.with_instance_id(id).map_err(|e| e.to_string())?,
+        None => mcast_config,
+    };
+    let mcast_config = match config.device_timeout_ms {
+        Some(ms) => mcast_config.with_device_timeout(Duration::from_millis(ms)),
+        None => mcast_config,
+    };
+    let mcast_config = mcast_config
+        .with_compress_payloads(config.compress_payloads.unwrap_or(false))
+        .with_send_on_all_interfaces(config.send_on_all_interfaces.unwrap_or(false))
+        .with_max_message_size(config.max_message_size.unwrap_or(MAX_MESSAGE_SIZE));
+    let mcast_config = match config.dual_stack_ip.as_deref().filter(|ip| !ip.is_empty()) {
+        Some(ip) => {
+            let ip = ip.parse().map_err(|e| format!("Invalid dual-stack IP address: {}", e))?;
+            mcast_config.with_dual_stack_ip(Some(ip))
+        }
+        None => mcast_config,
+    };
+    mcast_config.validate().map_err(|e| e.to_string())?;
+    set_interface_preference(InterfacePreference {
+        names: config.preferred_interfaces.unwrap_or_default(),
+        prefer_physical: config.prefer_physical_interfaces.unwrap_or(true),
+    });
 
-    let instance_id = generate_instance_id();
+    let instance_id = mcast_config.resolve_instance_id();
     
     *state.instance_id.lock().unwrap() = Some(instance_id.clone());
     *state.config.lock().unwrap() = Some(mcast_config.clone());
 
+    if let Err(e) = spawn_server(&state, mcast_config.clone(), instance_id.clone(), app.clone()) {
+        *state.instance_id.lock().unwrap() = None;
+        *state.config.lock().unwrap() = None;
+        return Err(e);
+    }
+    spawn_client(&state, mcast_config, instance_id.clone(), app);
+
+    Ok(instance_id)
+}
+
+/// Joins the group and starts the listener thread, waiting until the join has
+/// either worked or failed.
+fn spawn_server(
+    state: &AppState,
+    server_config: MulticastConfig,
+    server_id: String,
+    app_server: tauri::AppHandle,
+) -> Result<(), String> {
     state.server_running.store(true, Ordering::Relaxed);
-    state.client_running.store(true, Ordering::Relaxed);
 
     let server_flag = Arc::clone(&state.server_running);
-    let server_id = instance_id.clone();
-    let server_config = mcast_config.clone();
-    let app_server = app.clone();
+    let roster_path = device_roster_path(&app_server);
+    let readiness = ServerReadiness::new();
+    let server_readiness = readiness.clone();
     
-    thread::spawn(move || {
-        let mcast_addr = std::net::SocketAddr::new(server_config.ip, server_config.port);
-        
-        let listener = match join_multicast(mcast_addr, server_config.interface_name.as_deref()) {
-            Ok(sock) => sock,
-            Err(e) => {
-                let _ = app_server.emit("multicast-error", format!("Failed to join: {}", e));
-                return;
+    let spawned = thread::Builder::new().name("mcast-server".to_string()).spawn(move || {
+        let mut listeners = Vec::new();
+        for group in server_config.groups() {
+            let mcast_addr = std::net::SocketAddr::new(group, server_config.port);
+            match join_multicast(mcast_addr, server_config.interface_name.as_deref()) {
+                Ok(sock) => listeners.push(sock),
+                Err(e) => {
+                    server_readiness.mark_failed(&e);
+                    let _ = app_server.emit("multicast-error", format!("Failed to join {}: {}", group, e));
+                    return;
+                }
             }
-        };
+        }
 
+        server_readiness.mark_joined();
         let _ = app_server.emit("multicast-status", "Server started");
+
+        if let Some(path) = &roster_path {
+            if let Err(e) = load_device_roster(path, server_config.device_timeout) {
+                log::warn!("[ROSTER] Failed to restore devices from {:?}: {}", path, e);
+            }
+        }
         
-        let cleanup_flag = Arc::clone(&server_flag);
-        thread::spawn(move || {
-            while cleanup_flag.load(Ordering::Relaxed) {
-                thread::sleep(std::time::Duration::from_secs(2));
-                let removed = multicast::cleanup_inactive_devices(std::time::Duration::from_secs(14));
+        let cleanup = multicast::spawn_device_cleanup(Arc::clone(&server_flag), server_config.device_timeout);
+
+        // One receive loop per group; they all feed the same device list.
+        thread::scope(|scope| {
+            for listener in &listeners {
+                scope.spawn(|| {
+                    receive_messages(&server_flag, &server_id, &server_config, listener, |msg, msg_type, _| {
+                        emit_message(&app_server, msg, msg_type)
+                    })
+                });
             }
         });
-        
-        let mut buf = [std::mem::MaybeUninit::<u8>::uninit(); 1024];
-        
-        while server_flag.load(Ordering::Relaxed) {
-            match listener.recv_from(&mut buf) {
-                Ok((len, _)) => {
-                    let data = unsafe {
-                        std::slice::from_raw_parts(buf.as_ptr() as *const u8, len)
-                    };
-                    
-                    if let Ok(msg) = Message::deserialize(data) {
-                        if msg.uuid != server_id {
-                            let msg_type_str = match msg.msg_type {
-                                multicast::MSG_TYPE_HEARTBEAT => {
-                                    multicast::update_device(msg.uuid.clone(), msg.text.clone());
-                                    "HEARTBEAT"
-                                },
-                                multicast::MSG_TYPE_DISCONNECT => {
-                                    multicast::remove_device(&msg.uuid);
-                                    "DISCONNECT"
-                                },
-                                _ => "UNKNOWN",
-                            };
-                            
-                            let event = MessageEvent {
-                                msg_type: msg_type_str.to_string(),
-                                uuid: msg.uuid,
-                                text: msg.text,
-                                timestamp: chrono::Local::now().format("%H:%M:%S").to_string(),
-                            };
-                            
-                            let _ = app_server.emit("multicast-message", event);
-                        }
-                    }
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock 
-                    || e.kind() == std::io::ErrorKind::TimedOut => {
-                    continue;
-                }
-                Err(_) => continue,
+
+        let _ = cleanup.join();
+        if let Some(path) = &roster_path {
+            if let Err(e) = save_device_roster(path) {
+                log::error!("[ROSTER] Failed to save devices to {:?}: {}", path, e);
             }
         }
-
         multicast::ACTIVE_DEVICES.lock().unwrap().clear();
         let _ = app_server.emit("multicast-status", "Server stopped");
     });
+    let server_handle = match spawned {
+        Ok(handle) => handle,
+        Err(e) => {
+            state.server_running.store(false, Ordering::Relaxed);
+            return Err(format!("Failed to start the server thread: {}", e));
+        }
+    };
+
+    if let JoinState::Failed(e) = readiness.wait(MULTICAST_JOIN_TIMEOUT) {
+        state.server_running.store(false, Ordering::Relaxed);
+        let _ = server_handle.join();
+        return Err(format!("Failed to join multicast group: {}", e));
+    }
+
+    *state.server_thread.lock().unwrap() = Some(server_handle);
+    Ok(())
+}
+
+/// Passes a message the shared receive loop accepted on to the frontend.
+fn emit_message(app: &tauri::AppHandle, msg: Message, msg_type: &str) {
+    let event = MessageEvent {
+        msg_type: msg_type.to_string(),
+        uuid: msg.uuid,
+        text: msg.text,
+        timestamp: chrono::Local::now().format("%H:%M:%S").to_string(),
+    };
+    let _ = app.emit("multicast-message", event);
+}
+
+/// Starts the heartbeat sender thread, unpaused.
+fn spawn_client(
+    state: &AppState,
+    client_config: MulticastConfig,
+    client_id: String,
+    app_client: tauri::AppHandle,
+) {
+    state.client_running.store(true, Ordering::Relaxed);
+    state.client_paused.store(false, Ordering::Relaxed);
 
     let client_flag = Arc::clone(&state.client_running);
-    let client_id = instance_id.clone();
-    let client_config = mcast_config.clone();
-    let app_client = app.clone();
+    let client_paused = Arc::clone(&state.client_paused);
     
-    thread::spawn(move || {
+    let spawned = thread::Builder::new().name("mcast-client".to_string()).spawn(move || {
         thread::sleep(std::time::Duration::from_millis(500));
         
-        let mcast_addr = std::net::SocketAddr::new(client_config.ip, client_config.port);
-        let interface_ref = client_config.interface_name.as_deref();
-        
-        let sender = match create_sender(&mcast_addr, interface_ref) {
-            Ok(sock) => sock,
+        let mut sender = match create_multicast_sender(&client_config) {
+            Ok(sender) => sender,
             Err(e) => {
                 let _ = app_client.emit("multicast-error", format!("Failed to create sender: {}", e));
                 return;
@@ -147,14 +270,23 @@ fn start_multicast(
         };
         
         let _ = app_client.emit("multicast-status", "Client started");
+        if client_config.send_on_all_interfaces {
+            let interfaces = sender.interfaces().join(", ");
+            let _ = app_client.emit("multicast-status", format!("Sending on {}", interfaces));
+        }
         
-        let sock_addr = SockAddr::from(mcast_addr);
         let mut counter = 0;
         
         *MESSAGE_TEXT.lock().unwrap() = client_config.message.clone();
         
         while client_flag.load(Ordering::Relaxed) {
+            if client_paused.load(Ordering::Relaxed) {
+                thread::sleep(std::time::Duration::from_millis(100));
+                continue;
+            }
+
             counter += 1;
+            let last_sent = Instant::now();
             
             let text = MESSAGE_TEXT.lock().unwrap().clone();
             let message = Message {
@@ -162,36 +294,42 @@ fn start_multicast(
                 length: text.len() as u16,
                 uuid: client_id.clone(),
                 text: format!("{} #{}", text, counter),
+                advertised_max_size: Some(client_config.max_message_size as u16),
             };
             
-            if let Ok(data) = message.serialize() {
-                let _ = sender.send_to(&data, &sock_addr);
+            if let Ok(data) = message.serialize_within(client_config.compress_payloads, client_config.max_message_size) {
+                let _ = sender.send(&data);
                 let _ = app_client.emit("multicast-sent", counter);
             }
             
-            for _ in 0..30 {
-                if !client_flag.load(Ordering::Relaxed) {
+            let ticks = (client_config.next_heartbeat_delay().as_millis() / 100).max(1);
+            for _ in 0..ticks {
+                if !client_flag.load(Ordering::Relaxed)
+                    || client_paused.load(Ordering::Relaxed)
+                    || take_heartbeat_request(last_sent, client_config.min_message_interval)
+                {
                     break;
                 }
                 thread::sleep(std::time::Duration::from_millis(100));
             }
         }
         
-        send_disconnect_message(&sender, &sock_addr, &client_id);
+        sender.send_disconnect(&client_id);
         let _ = app_client.emit("multicast-status", "Client stopped");
     });
+    let client_handle = spawned.expect("failed to spawn mcast-client thread");
 
-    Ok(instance_id)
+    *state.client_thread.lock().unwrap() = Some(client_handle);
 }
 
 #[tauri::command]
 fn stop_multicast(state: State<AppState>) -> Result<(), String> {
-    if !state.server_running.load(Ordering::Relaxed) {
+    if state.config.lock().unwrap().is_none() {
         return Err("Multicast not running".to_string());
     }
 
-    state.client_running.store(false, Ordering::Relaxed);
-    state.server_running.store(false, Ordering::Relaxed);
+    stop_thread(&state.client_running, &state.client_thread, "Client");
+    stop_thread(&state.server_running, &state.server_thread, "Server");
 
     *state.instance_id.lock().unwrap() = None;
     *state.config.lock().unwrap() = None;
@@ -199,9 +337,83 @@ fn stop_multicast(state: State<AppState>) -> Result<(), String> {
     Ok(())
 }
 
+/// The running session's config and instance id, for restarting one half of it.
+fn current_session(state: &AppState) -> Result<(MulticastConfig, String), String> {
+    let config = state.config.lock().unwrap().clone().ok_or("Multicast not running")?;
+    let instance_id = state.instance_id.lock().unwrap().clone().ok_or("Multicast not running")?;
+    Ok((config, instance_id))
+}
+
+/// Starts listening again after `stop_server`, keeping the running client.
+#[tauri::command]
+fn start_server(state: State<AppState>, app: tauri::AppHandle) -> Result<(), String> {
+    let (config, instance_id) = current_session(&state)?;
+    if state.server_running.load(Ordering::Relaxed) || thread_alive(&state.server_thread) {
+        return Err("Server already running".to_string());
+    }
+    spawn_server(&state, config, instance_id, app)
+}
+
+/// Stops listening and clears the device list while the client keeps sending
+/// heartbeats.
+#[tauri::command]
+fn stop_server(state: State<AppState>) -> Result<(), String> {
+    current_session(&state)?;
+    if !state.server_running.load(Ordering::Relaxed) {
+        return Err("Server not running".to_string());
+    }
+    stop_thread(&state.server_running, &state.server_thread, "Server");
+    Ok(())
+}
+
+#[tauri::command]
+fn start_client(state: State<AppState>, app: tauri::AppHandle) -> Result<(), String> {
+    let (config, instance_id) = current_session(&state)?;
+    if state.client_running.load(Ordering::Relaxed) || thread_alive(&state.client_thread) {
+        return Err("Client already running".to_string());
+    }
+    spawn_client(&state, config, instance_id, app);
+    Ok(())
+}
+
+/// Stops sending heartbeats (with a disconnect message) while the server keeps
+/// listening; the device list is left alone.
+#[tauri::command]
+fn stop_client(state: State<AppState>) -> Result<(), String> {
+    current_session(&state)?;
+    if !state.client_running.load(Ordering::Relaxed) {
+        return Err("Client not running".to_string());
+    }
+    stop_thread(&state.client_running, &state.client_thread, "Client");
+    Ok(())
+}
+
+#[tauri::command]
+fn pause_multicast(state: State<AppState>, app: tauri::AppHandle) -> Result<(), String> {
+    if !state.client_running.load(Ordering::Relaxed) {
+        return Err("Multicast not running".to_string());
+    }
+
+    state.client_paused.store(true, Ordering::Relaxed);
+    let _ = app.emit("multicast-status", "Client paused");
+    Ok(())
+}
+
+#[tauri::command]
+fn resume_multicast(state: State<AppState>, app: tauri::AppHandle) -> Result<(), String> {
+    if !state.client_running.load(Ordering::Relaxed) {
+        return Err("Multicast not running".to_string());
+    }
+
+    state.client_paused.store(false, Ordering::Relaxed);
+    let _ = app.emit("multicast-status", "Client resumed");
+    Ok(())
+}
+
 #[tauri::command]
 fn update_message(message: String, state: State<AppState>) -> Result<(), String> {
     if let Some(ref mut config) = *state.config.lock().unwrap() {
+        check_message_text_within(&message, config.max_message_size).map_err(|e| e.to_string())?;
         config.message = message.clone();
         *MESSAGE_TEXT.lock().unwrap() = message;
         Ok(())
@@ -212,7 +424,21 @@ fn update_message(message: String, state: State<AppState>) -> Result<(), String>
 
 #[tauri::command]
 fn get_status(state: State<AppState>) -> bool {
-    state.server_running.load(Ordering::Relaxed)
+    state.config.lock().unwrap().is_some()
+}
+
+#[derive(Clone, Serialize)]
+struct ComponentStatus {
+    server_running: bool,
+    client_running: bool,
+}
+
+#[tauri::command]
+fn get_component_status(state: State<AppState>) -> ComponentStatus {
+    ComponentStatus {
+        server_running: state.server_running.load(Ordering::Relaxed),
+        client_running: state.client_running.load(Ordering::Relaxed),
+    }
 }
 
 #[tauri::command]
@@ -220,12 +446,19 @@ fn get_instance_id(state: State<AppState>) -> Option<String> {
     state.instance_id.lock().unwrap().clone()
 }
 
+/// The IPv6 interface in use as `name (index N)`, named or auto-detected.
+#[tauri::command]
+fn get_selected_interface() -> Option<String> {
+    selected_ipv6_interface().map(|info| format!("{} (index {})", info.name, info.index))
+}
+
 #[derive(Clone, Serialize)]
 struct DeviceData {
     uuid: String,
     last_message: String,
     message_count: u32,
     seconds_since_seen: u64,
+    stale: bool,
 }
 
 #[tauri::command]
@@ -239,28 +472,96 @@ fn get_active_devices() -> Vec<DeviceData> {
                 last_message: dev.last_message.clone(),
                 message_count: dev.message_count,
                 seconds_since_seen: dev.last_seen.elapsed().as_secs(),
+                stale: dev.stale,
             }
         })
         .collect()
 }
 
+#[tauri::command]
+async fn query_device(uuid: String, state: State<'_, AppState>) -> Result<DeviceData, String> {
+    let config = state.config.lock().unwrap().clone().ok_or("Multicast not running")?;
+    let instance_id = state.instance_id.lock().unwrap().clone().ok_or("Multicast not running")?;
+
+    // Asked on every group, as the device may only be listening on one of them.
+    let sent_at = Instant::now();
+    let mut sent = false;
+    let mut last_error = None;
+    for group in config.groups() {
+        let result = create_scoped_sender(&config.for_group(group))
+            .and_then(|(sender, destination)| send_query(&sender, &SockAddr::from(destination), &instance_id, &uuid));
+        match result {
+            Ok(_) => sent = true,
+            Err(e) => last_error = Some(e.to_string()),
+        }
+    }
+    if !sent {
+        return Err(last_error.unwrap_or_else(|| "No group to send the query on".to_string()));
+    }
+
+    match wait_for_device_update(&uuid, sent_at, DEVICE_QUERY_TIMEOUT) {
+        Some(dev) => Ok(DeviceData {
+            uuid: dev.uuid,
+            last_message: dev.last_message,
+            message_count: dev.message_count,
+            seconds_since_seen: dev.last_seen.elapsed().as_secs(),
+            stale: dev.stale,
+        }),
+        None => Err(format!("Device {} did not respond within {:?}", uuid, DEVICE_QUERY_TIMEOUT)),
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct LogLine {
+    timestamp: String,
+    level: String,
+    message: String,
+}
+
+#[tauri::command]
+fn get_recent_logs() -> Vec<LogLine> {
+    multicast::get_recent_logs()
+        .into_iter()
+        .map(|entry| LogLine {
+            timestamp: chrono::DateTime::<chrono::Local>::from(entry.time).format("%H:%M:%S").to_string(),
+            level: entry.level.to_string(),
+            message: entry.message,
+        })
+        .collect()
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let _ = multicast::install_log_capture(log::LevelFilter::Info, None);
+
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .manage(AppState {
             server_running: Arc::new(AtomicBool::new(false)),
             client_running: Arc::new(AtomicBool::new(false)),
+            client_paused: Arc::new(AtomicBool::new(false)),
             instance_id: Mutex::new(None),
             config: Mutex::new(None),
+            server_thread: Mutex::new(None),
+            client_thread: Mutex::new(None),
         })
         .invoke_handler(tauri::generate_handler![
             start_multicast,
             stop_multicast,
+            start_server,
+            stop_server,
+            start_client,
+            stop_client,
+            pause_multicast,
+            resume_multicast,
             update_message,
             get_status,
+            get_component_status,
+            get_selected_interface,
             get_instance_id,
-            get_active_devices
+            get_active_devices,
+            query_device,
+            get_recent_logs
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,156 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use log::{info, error};
+use socket2::SockAddr;
+
+use crate::{get_ipv6_interface, new_socket, with_ipv6_scope, MulticastConfig};
+
+/// What [`diagnose_membership`] found out about joining the configured group.
+#[derive(Debug, Clone)]
+pub struct MembershipReport {
+    pub group: SocketAddr,
+    /// Interface the join was made on; `None` leaves the choice to the OS
+    /// (always the case for IPv4, which joins on `0.0.0.0`).
+    pub interface_name: Option<String>,
+    pub interface_index: u32,
+    pub join_result: Result<(), String>,
+    pub bind_result: Result<(), String>,
+    pub local_addr: Option<SocketAddr>,
+    /// Interfaces on which the OS lists the group as joined while the socket was
+    /// open, or `None` where the membership tables can't be read (non-Linux).
+    pub os_memberships: Option<Vec<String>>,
+}
+
+impl MembershipReport {
+    /// Joined and bound, and the OS confirms the membership where it can tell.
+    pub fn is_healthy(&self) -> bool {
+        self.join_result.is_ok()
+            && self.bind_result.is_ok()
+            && self.os_memberships.as_ref().is_none_or(|interfaces| !interfaces.is_empty())
+    }
+}
+
+impl fmt::Display for MembershipReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Multicast membership report")?;
+        writeln!(f, "  group:            {}", self.group)?;
+        match &self.interface_name {
+            Some(name) => writeln!(f, "  interface:        {} (index {})", name, self.interface_index)?,
+            None => writeln!(f, "  interface:        chosen by the OS (index {})", self.interface_index)?,
+        }
+        match &self.join_result {
+            Ok(()) => writeln!(f, "  join:             ok")?,
+            Err(e) => writeln!(f, "  join:             FAILED: {}", e)?,
+        }
+        match &self.bind_result {
+            Ok(()) => writeln!(f, "  bind:             ok")?,
+            Err(e) => writeln!(f, "  bind:             FAILED: {}", e)?,
+        }
+        match self.local_addr {
+            Some(addr) => writeln!(f, "  local address:    {}", addr)?,
+            None => writeln!(f, "  local address:    unknown")?,
+        }
+        match &self.os_memberships {
+            Some(interfaces) if interfaces.is_empty() => writeln!(f, "  OS membership:    NOT LISTED on any interface")?,
+            Some(interfaces) => writeln!(f, "  OS membership:    listed on {}", interfaces.join(", "))?,
+            None => writeln!(f, "  OS membership:    not available on this platform")?,
+        }
+        write!(f, "  result:           {}", if self.is_healthy() { "OK" } else { "PROBLEM" })
+    }
+}
+
+/// Joins the group from `config` the same way the server does and reports each
+/// step, checking the OS membership tables before the socket is closed again.
+pub fn diagnose_membership(config: &MulticastConfig) -> io::Result<MembershipReport> {
+    let group = SocketAddr::new(config.ip, config.port);
+    let socket = new_socket(&group)?;
+
+    let mut interface_name = None;
+    let mut interface_index = 0;
+    let mut bind_addr = group;
+
+    let join_result = match config.ip {
+        IpAddr::V4(ref group_v4) => socket.join_multicast_v4(group_v4, &Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(ref group_v6) => {
+            if let Some(info) = get_ipv6_interface(config.interface_name.as_deref()) {
+                interface_index = info.index;
+                interface_name = Some(info.name);
+            }
+            bind_addr = with_ipv6_scope(group, interface_index);
+            socket
+                .join_multicast_v6(group_v6, interface_index)
+                .and_then(|()| socket.set_only_v6(true))
+        }
+    }
+    .map_err(|e| e.to_string());
+
+    match &join_result {
+        Ok(()) => info!("[DIAG] Joined {} on interface index {}", config.ip, interface_index),
+        Err(e) => error!("[DIAG] Joining {} on interface index {} failed: {}", config.ip, interface_index, e),
+    }
+
+    let bind_result = socket.bind(&SockAddr::from(bind_addr)).map_err(|e| e.to_string());
+    let local_addr = socket.local_addr().ok().and_then(|addr| addr.as_socket());
+    let os_memberships = os_group_memberships(config.ip);
+
+    Ok(MembershipReport {
+        group,
+        interface_name,
+        interface_index,
+        join_result,
+        bind_result,
+        local_addr,
+        os_memberships,
+    })
+}
+
+/// Names of the interfaces the kernel lists `group` on, from `/proc/net/igmp`
+/// or `/proc/net/igmp6`.
+#[cfg(target_os = "linux")]
+fn os_group_memberships(group: IpAddr) -> Option<Vec<String>> {
+    let mut interfaces = Vec::new();
+    match group {
+        IpAddr::V4(group_v4) => {
+            // Device lines start with the index; their groups follow on
+            // tab-indented lines as the raw network-order address in hex.
+            let table = std::fs::read_to_string("/proc/net/igmp").ok()?;
+            let mut device: Option<String> = None;
+            for line in table.lines().skip(1) {
+                if line.starts_with('\t') {
+                    let listed = line
+                        .split_whitespace()
+                        .next()
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .map(|raw| Ipv4Addr::from(raw.to_ne_bytes()));
+                    if listed == Some(group_v4) && let Some(name) = &device {
+                        interfaces.push(name.clone());
+                    }
+                } else {
+                    device = line.split_whitespace().nth(1).map(str::to_string);
+                }
+            }
+        }
+        IpAddr::V6(group_v6) => {
+            let table = std::fs::read_to_string("/proc/net/igmp6").ok()?;
+            for line in table.lines() {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let listed = fields
+                    .get(2)
+                    .and_then(|hex| u128::from_str_radix(hex, 16).ok())
+                    .map(Ipv6Addr::from);
+                if listed == Some(group_v6) {
+                    interfaces.push(fields[1].to_string());
+                }
+            }
+        }
+    }
+    interfaces.dedup();
+    Some(interfaces)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn os_group_memberships(_group: IpAddr) -> Option<Vec<String>> {
+    None
+}
@@ -1,22 +1,34 @@
 use std::io;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::thread;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 use std::mem::MaybeUninit;
 use std::collections::HashMap;
 use uuid_rs::v4;
 use lazy_static::lazy_static;
 use socket2::{Domain, Protocol, SockAddr, Socket, Type};
-use log::{info, error};
+use log::{debug, info, warn, error};
 use if_addrs::get_if_addrs;
 #[cfg(target_os = "macos")]
 use std::os::fd::AsRawFd;
 
+mod diagnostics;
+mod recent_logs;
+mod roster;
+mod senders;
+
+pub use diagnostics::{diagnose_membership, MembershipReport};
+pub use recent_logs::{clear_recent_logs, get_recent_logs, install_log_capture, LogEntry, RECENT_LOG_CAPACITY};
+pub use roster::{load_device_roster, save_device_roster};
+pub use senders::{create_multicast_sender, MulticastSender, INTERFACE_REFRESH_INTERVAL};
+
 lazy_static! {
     pub static ref MESSAGE_TEXT: Mutex<String> = Mutex::new(String::from("Hello from client"));
     pub static ref ACTIVE_DEVICES: Mutex<HashMap<String, DeviceInfo>> = Mutex::new(HashMap::new());
+    static ref INTERFACE_PREFERENCE: Mutex<InterfacePreference> = Mutex::new(InterfacePreference::default());
+    static ref SELECTED_IPV6_INTERFACE: Mutex<Option<Ipv6InterfaceInfo>> = Mutex::new(None);
 }
 
 #[derive(Debug, Clone)]
@@ -25,6 +37,8 @@ pub struct DeviceInfo {
     pub last_seen: Instant,
     pub last_message: String,
     pub message_count: u32,
+    /// Restored by [`load_device_roster`] and not heard from since.
+    pub stale: bool,
 }
 
 impl DeviceInfo {
@@ -34,6 +48,7 @@ impl DeviceInfo {
             last_seen: Instant::now(),
             last_message: message,
             message_count: 1,
+            stale: false,
         }
     }
 
@@ -41,6 +56,7 @@ impl DeviceInfo {
         self.last_seen = Instant::now();
         self.last_message = message;
         self.message_count += 1;
+        self.stale = false;
     }
 
     pub fn is_alive(&self, timeout: Duration) -> bool {
@@ -50,7 +66,40 @@ impl DeviceInfo {
 
 pub const MSG_TYPE_HEARTBEAT: u8 = 0;
 pub const MSG_TYPE_DISCONNECT: u8 = 1;
+/// Asks the node whose UUID is in the message text to send a heartbeat right away.
+pub const MSG_TYPE_QUERY: u8 = 2;
+/// Default for [`MulticastConfig::max_message_size`], small enough to stay
+/// within any Ethernet MTU.
 pub const MAX_MESSAGE_SIZE: usize = 500;
+/// What the payload adds to the user's text: the 36-byte UUID and the longer of
+/// the ` #<counter>` and ` - Disconnecting` suffixes.
+const MESSAGE_TEXT_OVERHEAD: usize = 36 + " - Disconnecting".len();
+/// Longest user message text that still fits in `MAX_MESSAGE_SIZE`.
+pub const MAX_MESSAGE_TEXT_SIZE: usize = MAX_MESSAGE_SIZE - MESSAGE_TEXT_OVERHEAD;
+/// Smallest configurable `max_message_size`, leaving room for 48 bytes of text.
+pub const MIN_MESSAGE_SIZE: usize = MESSAGE_TEXT_OVERHEAD + 48;
+/// Largest configurable `max_message_size`: what fits in one UDP datagram next
+/// to the 3-byte header and the 2-byte size trailer heartbeats carry.
+pub const MAX_CONFIGURABLE_MESSAGE_SIZE: usize = 65_507 - 3 - 2;
+/// Set in the type byte when the payload (UUID and text) is deflate-compressed;
+/// [`Message::deserialize`] clears it again, so `msg_type` is always the plain type.
+pub const MSG_FLAG_COMPRESSED: u8 = 0x80;
+/// Largest payload a compressed message may expand to. Only the compressed size
+/// has to fit in `MAX_MESSAGE_SIZE`; this caps what a receiver will inflate.
+pub const MAX_DECOMPRESSED_MESSAGE_SIZE: usize = 4096;
+pub const DEFAULT_MIN_MESSAGE_INTERVAL: Duration = Duration::from_millis(500);
+/// A TTL (hop limit on IPv6) of 1 keeps multicast traffic on the local link;
+/// raising it lets packets cross multicast routers.
+pub const DEFAULT_MULTICAST_TTL: u32 = 1;
+/// TTLs above this reach past a single site; they're refused unless
+/// `allow_wide_scope` is set.
+pub const MAX_SAFE_MULTICAST_TTL: u32 = 32;
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(3);
+/// A device silent for this long is dropped from `ACTIVE_DEVICES`.
+pub const DEFAULT_DEVICE_TIMEOUT: Duration = Duration::from_secs(14);
+/// The device timeout has to cover at least this many of the longest heartbeat
+/// intervals, or one late or lost heartbeat makes a device drop out and reappear.
+pub const MIN_HEARTBEATS_PER_DEVICE_TIMEOUT: u32 = 2;
 
 #[derive(Clone, Debug)]
 pub struct MulticastConfig {
@@ -58,6 +107,33 @@ pub struct MulticastConfig {
     pub port: u16,
     pub message: String,
     pub interface_name: Option<String>,
+    pub min_message_interval: Duration,
+    pub multicast_ttl: u32,
+    pub allow_wide_scope: bool,
+    pub loopback: bool,
+    pub heartbeat_interval: Duration,
+    /// Each heartbeat interval is randomized within ±this fraction (0.0..=1.0)
+    /// so clients started together don't send in lockstep.
+    pub heartbeat_jitter: f64,
+    /// Fixed instance id to use instead of a random one; see [`MulticastConfig::with_instance_id`].
+    pub instance_id: Option<String>,
+    /// Deflate outgoing payloads when that makes them smaller. Receivers older
+    /// than this option can't read compressed messages, so it is off by default.
+    pub compress_payloads: bool,
+    pub device_timeout: Duration,
+    /// Send every heartbeat out of each suitable interface instead of just the
+    /// selected one, so nodes on all connected LANs discover this one.
+    pub send_on_all_interfaces: bool,
+    /// Largest payload this node sends, and the limit its message text is checked
+    /// against. It is announced in every heartbeat so peers configured
+    /// differently get flagged. Datagrams above the path MTU (about 1472 bytes of
+    /// UDP payload on plain Ethernet, 8972 with jumbo frames) are fragmented by
+    /// IP: losing any fragment loses the whole heartbeat, and some networks drop
+    /// fragments outright.
+    pub max_message_size: usize,
+    /// Group of the other address family to announce and listen on as well, so
+    /// peers on IPv4-only and IPv6-only stacks all discover this node. Same port.
+    pub dual_stack_ip: Option<IpAddr>,
 }
 
 impl Default for MulticastConfig {
@@ -67,6 +143,18 @@ impl Default for MulticastConfig {
             port: 8888,
             message: String::from("Hello from client"),
             interface_name: None,
+            min_message_interval: DEFAULT_MIN_MESSAGE_INTERVAL,
+            multicast_ttl: DEFAULT_MULTICAST_TTL,
+            allow_wide_scope: false,
+            loopback: true,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            heartbeat_jitter: 0.0,
+            instance_id: None,
+            compress_payloads: false,
+            device_timeout: DEFAULT_DEVICE_TIMEOUT,
+            send_on_all_interfaces: false,
+            max_message_size: MAX_MESSAGE_SIZE,
+            dual_stack_ip: None,
         }
     }
 }
@@ -86,8 +174,169 @@ impl MulticastConfig {
             port, 
             message,
             interface_name,
+            min_message_interval: DEFAULT_MIN_MESSAGE_INTERVAL,
+            multicast_ttl: DEFAULT_MULTICAST_TTL,
+            allow_wide_scope: false,
+            loopback: true,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            heartbeat_jitter: 0.0,
+            instance_id: None,
+            compress_payloads: false,
+            device_timeout: DEFAULT_DEVICE_TIMEOUT,
+            send_on_all_interfaces: false,
+            max_message_size: MAX_MESSAGE_SIZE,
+            dual_stack_ip: None,
         })
     }
+
+    pub fn with_min_message_interval(mut self, interval: Duration) -> Self {
+        self.min_message_interval = interval;
+        self
+    }
+
+    pub fn with_multicast_ttl(mut self, ttl: u32) -> Self {
+        self.multicast_ttl = ttl;
+        self
+    }
+
+    pub fn with_allow_wide_scope(mut self, allow: bool) -> Self {
+        self.allow_wide_scope = allow;
+        self
+    }
+
+    pub fn with_loopback(mut self, loopback: bool) -> Self {
+        self.loopback = loopback;
+        self
+    }
+
+    pub fn with_heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    pub fn with_heartbeat_jitter(mut self, jitter: f64) -> Self {
+        self.heartbeat_jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Checks what can be checked before any socket is opened: the group (and
+    /// any dual-stack group, which must be of the other address family) is a
+    /// multicast address, the port is non-zero, the device timeout spans enough
+    /// heartbeats, the maximum message size is in range and the message text
+    /// fits in it.
+    pub fn validate(&self) -> io::Result<()> {
+        if !self.ip.is_multicast() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is not a multicast address (expected 224.0.0.0/4 for IPv4 or ff00::/8 for IPv6)",
+                    self.ip
+                ),
+            ));
+        }
+        if self.port == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Port must be between 1 and 65535"));
+        }
+        let longest_interval = self.heartbeat_interval.mul_f64(1.0 + self.heartbeat_jitter);
+        if self.device_timeout < longest_interval * MIN_HEARTBEATS_PER_DEVICE_TIMEOUT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Device timeout {:?} is shorter than {} heartbeat intervals (up to {:?} each); \
+                     devices would keep disappearing. Use a timeout of at least {:?}",
+                    self.device_timeout,
+                    MIN_HEARTBEATS_PER_DEVICE_TIMEOUT,
+                    longest_interval,
+                    longest_interval * MIN_HEARTBEATS_PER_DEVICE_TIMEOUT
+                ),
+            ));
+        }
+        if !(MIN_MESSAGE_SIZE..=MAX_CONFIGURABLE_MESSAGE_SIZE).contains(&self.max_message_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Maximum message size must be between {} and {} bytes",
+                    MIN_MESSAGE_SIZE, MAX_CONFIGURABLE_MESSAGE_SIZE
+                ),
+            ));
+        }
+        if let Some(dual_stack_ip) = self.dual_stack_ip {
+            if !dual_stack_ip.is_multicast() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a multicast address", dual_stack_ip),
+                ));
+            }
+            if dual_stack_ip.is_ipv6() == self.ip.is_ipv6() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Dual-stack group {} must be of the other address family than {}", dual_stack_ip, self.ip),
+                ));
+            }
+        }
+        check_message_text_within(&self.message, self.max_message_size)
+    }
+
+    pub fn with_device_timeout(mut self, timeout: Duration) -> Self {
+        self.device_timeout = timeout;
+        self
+    }
+
+    pub fn with_compress_payloads(mut self, compress: bool) -> Self {
+        self.compress_payloads = compress;
+        self
+    }
+
+    pub fn with_send_on_all_interfaces(mut self, all: bool) -> Self {
+        self.send_on_all_interfaces = all;
+        self
+    }
+
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    pub fn with_dual_stack_ip(mut self, ip: Option<IpAddr>) -> Self {
+        self.dual_stack_ip = ip;
+        self
+    }
+
+    /// The groups announced on and listened to: `ip`, then `dual_stack_ip`.
+    pub fn groups(&self) -> Vec<IpAddr> {
+        std::iter::once(self.ip).chain(self.dual_stack_ip).collect()
+    }
+
+    /// This configuration narrowed down to one of its [`groups`](Self::groups).
+    pub fn for_group(&self, ip: IpAddr) -> Self {
+        Self {
+            ip,
+            dual_stack_ip: None,
+            ..self.clone()
+        }
+    }
+
+    /// Uses `id` as this node's instance id, e.g. so tests can look for a known
+    /// UUID in another node's device list. `id` must be a hyphenated UUID.
+    pub fn with_instance_id(mut self, id: &str) -> io::Result<Self> {
+        self.instance_id = Some(parse_instance_id(id)?);
+        Ok(self)
+    }
+
+    /// The configured instance id, or a freshly generated random one.
+    pub fn resolve_instance_id(&self) -> String {
+        self.instance_id.clone().unwrap_or_else(generate_instance_id)
+    }
+
+    /// Delay until the next heartbeat, with jitter applied.
+    pub fn next_heartbeat_delay(&self) -> Duration {
+        if self.heartbeat_jitter <= 0.0 {
+            return self.heartbeat_interval;
+        }
+
+        let factor = 1.0 + self.heartbeat_jitter * (2.0 * random_unit() - 1.0);
+        self.heartbeat_interval.mul_f64(factor.max(0.0))
+    }
     
     pub fn is_ipv4(&self) -> bool {
         self.ip.is_ipv4()
@@ -98,14 +347,179 @@ impl MulticastConfig {
     }
 }
 
+/// Drops heartbeats from a single UUID that arrive faster than `min_interval`,
+/// so one noisy sender can't keep the `ACTIVE_DEVICES` lock busy.
+pub struct MessageRateLimiter {
+    min_interval: Duration,
+    last_processed: HashMap<String, Instant>,
+    throttled: HashMap<String, u32>,
+}
+
+impl MessageRateLimiter {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            last_processed: HashMap::new(),
+            throttled: HashMap::new(),
+        }
+    }
+
+    pub fn allow(&mut self, uuid: &str) -> bool {
+        if self.min_interval.is_zero() {
+            return true;
+        }
+
+        let now = Instant::now();
+        if let Some(last) = self.last_processed.get(uuid)
+            && now.duration_since(*last) < self.min_interval
+        {
+            let dropped = self.throttled.entry(uuid.to_string()).or_insert(0);
+            *dropped += 1;
+            if *dropped == 1 {
+                info!("[RATE] Throttling {}: messages faster than {:?}", uuid, self.min_interval);
+            }
+            return false;
+        }
+
+        if let Some(dropped) = self.throttled.remove(uuid) {
+            info!("[RATE] {} back under limit, dropped {} message(s)", uuid, dropped);
+        }
+        self.last_processed.insert(uuid.to_string(), now);
+
+        if self.last_processed.len() > 1024 {
+            let stale_after = self.min_interval.max(Duration::from_secs(60));
+            let throttled = &mut self.throttled;
+            self.last_processed.retain(|uuid, last| {
+                let fresh = now.duration_since(*last) < stale_after;
+                if !fresh {
+                    throttled.remove(uuid);
+                }
+                fresh
+            });
+        }
+        true
+    }
+
+    pub fn forget(&mut self, uuid: &str) {
+        self.last_processed.remove(uuid);
+        self.throttled.remove(uuid);
+    }
+}
+
+fn random_unit() -> f64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(Instant::now().elapsed().as_nanos());
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
 pub fn generate_instance_id() -> String {
     v4!().to_string()
 }
 
+/// Refuses message text too long to go out with every heartbeat; uncompressed,
+/// serializing it would fail on each tick and the node would silently stop
+/// sending.
+pub fn check_message_text(text: &str) -> io::Result<()> {
+    check_message_text_within(text, MAX_MESSAGE_SIZE)
+}
+
+/// Same as [`check_message_text`] for a node configured with a
+/// `max_message_size` other than the default.
+pub fn check_message_text_within(text: &str, max_message_size: usize) -> io::Result<()> {
+    let max_text = max_message_size.saturating_sub(MESSAGE_TEXT_OVERHEAD);
+    if text.len() > max_text {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Message too long: {} bytes (max {})", text.len(), max_text),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that `id` is a hyphenated UUID (the wire format reserves exactly 36
+/// bytes for it) and returns it lowercased.
+pub fn parse_instance_id(id: &str) -> io::Result<String> {
+    let id = id.trim();
+    let well_formed = id.len() == 36
+        && id.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        });
+
+    if !well_formed {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid instance id '{}': expected a UUID like 123e4567-e89b-12d3-a456-426614174000", id),
+        ));
+    }
+    Ok(id.to_ascii_lowercase())
+}
+
+static HEARTBEAT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes the client loop send its next heartbeat early.
+pub fn request_heartbeat() {
+    HEARTBEAT_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Consumes a pending [`request_heartbeat`], but only once `min_interval` has
+/// passed since the last heartbeat, so queries can't make us flood the group
+/// (or get our answer dropped by the receivers' rate limiters).
+pub fn take_heartbeat_request(last_sent: Instant, min_interval: Duration) -> bool {
+    last_sent.elapsed() >= min_interval && HEARTBEAT_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// Handles a received `MSG_TYPE_QUERY`; returns whether it was addressed to us.
+pub fn handle_query(msg: &Message, instance_id: &str) -> bool {
+    if msg.text.trim() != instance_id {
+        return false;
+    }
+    info!("[QUERY] {} asked for a heartbeat", msg.uuid);
+    request_heartbeat();
+    true
+}
+
+/// Waits until `uuid` has been heard from after `since`. Returns `None` if it
+/// stays silent for `timeout`, e.g. because the device has left.
+pub fn wait_for_device_update(uuid: &str, since: Instant, timeout: Duration) -> Option<DeviceInfo> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let updated = ACTIVE_DEVICES
+            .lock()
+            .unwrap()
+            .get(uuid)
+            .filter(|device| device.last_seen > since)
+            .cloned();
+        if updated.is_some() || Instant::now() >= deadline {
+            return updated;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Frames whose UUID doesn't parse (e.g. corrupted on the way) are dropped
+/// instead of showing up as phantom devices until they time out.
+///
+/// Every heartbeat text ends in its own counter, so a repeat of the last one is
+/// the same heartbeat arriving over a second group or interface: it keeps the
+/// device alive without counting as another message.
 pub fn update_device(uuid: String, message: String) {
+    if parse_instance_id(&uuid).is_err() {
+        debug!("[DEVICES] Ignoring message with malformed UUID {:?}", uuid);
+        return;
+    }
+
     let mut devices = ACTIVE_DEVICES.lock().unwrap();
     
     if let Some(device) = devices.get_mut(&uuid) {
+        if !device.stale && device.last_message == message {
+            device.last_seen = Instant::now();
+            debug!("[DEVICES] Same heartbeat from {} received again", uuid);
+            return;
+        }
         device.update(message);
         info!("[DEVICES] Updated device: {} (total: {})", uuid, devices.len());
     } else {
@@ -151,20 +565,79 @@ pub fn get_active_device_count() -> usize {
     count
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Ipv6InterfaceInfo {
     pub index: u32,
     pub name: String,
 }
 
+/// Name prefixes of interfaces that usually aren't the LAN: container bridges,
+/// hypervisor adapters, VPN tunnels and Apple's peer-to-peer links.
+const VIRTUAL_INTERFACE_PREFIXES: [&str; 17] = [
+    "docker", "br-", "veth", "virbr", "vmnet", "vboxnet", "vEthernet", "tun", "tap", "utun", "wg", "zt",
+    "tailscale", "ppp", "ipsec", "awdl", "llw",
+];
+
+/// How auto-detection ranks IPv6 interfaces when no interface is named.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfacePreference {
+    /// Interface names in order of preference; a trailing `*` matches any
+    /// suffix (`wl*`, `en0`). Listed interfaces beat unlisted ones.
+    pub names: Vec<String>,
+    /// Rank interfaces that look virtual (see `VIRTUAL_INTERFACE_PREFIXES`)
+    /// after physical ones.
+    pub prefer_physical: bool,
+}
+
+impl Default for InterfacePreference {
+    fn default() -> Self {
+        Self { names: Vec::new(), prefer_physical: true }
+    }
+}
+
+impl InterfacePreference {
+    fn name_rank(&self, name: &str) -> usize {
+        self.names
+            .iter()
+            .position(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            })
+            .unwrap_or(self.names.len())
+    }
+}
+
+/// Used by every auto-detection from now on.
+pub fn set_interface_preference(preference: InterfacePreference) {
+    *INTERFACE_PREFERENCE.lock().unwrap() = preference;
+}
+
+pub fn interface_preference() -> InterfacePreference {
+    INTERFACE_PREFERENCE.lock().unwrap().clone()
+}
+
+/// The IPv6 interface the last join or sender used, named or auto-detected.
+pub fn selected_ipv6_interface() -> Option<Ipv6InterfaceInfo> {
+    SELECTED_IPV6_INTERFACE.lock().unwrap().clone()
+}
+
+fn is_virtual_interface(name: &str) -> bool {
+    VIRTUAL_INTERFACE_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// A given `interface_name` is resolved directly by name, which works even where
+/// listing the system's interfaces is blocked; enumeration is only the fallback.
 pub fn get_ipv6_interface(interface_name: Option<&str>) -> Option<Ipv6InterfaceInfo> {
     if let Some(name) = interface_name {
         match get_interface_index(name) {
             Ok(index) => {
-                info!("[IPv6] Using specified interface: {} (index: {})", name, index);
-                return Some(Ipv6InterfaceInfo {
+                info!("[IPv6] Using specified interface: {} (index: {}, resolved directly by name)", name, index);
+                let info = Ipv6InterfaceInfo {
                     index,
                     name: name.to_string(),
-                });
+                };
+                *SELECTED_IPV6_INTERFACE.lock().unwrap() = Some(info.clone());
+                return Some(info);
             }
             Err(e) => {
                 error!("[IPv6] Failed to get index for interface '{}': {}. Falling back to auto-detection.", name, e);
@@ -172,51 +645,77 @@ pub fn get_ipv6_interface(interface_name: Option<&str>) -> Option<Ipv6InterfaceI
         }
     }
 
-    find_ipv6_multicast_interface()
+    let found = find_ipv6_multicast_interface();
+    if let Some(info) = found.as_ref() {
+        info!("[IPv6] Using interface {} (index: {}, found by enumeration)", info.name, info.index);
+    }
+    *SELECTED_IPV6_INTERFACE.lock().unwrap() = found.clone();
+    found
 }
 
+/// Picks the best IPv6-capable interface by the current [`InterfacePreference`]:
+/// preferred names first, then physical over virtual, then a global address
+/// over a link-local one; ties go to enumeration order.
 pub fn find_ipv6_multicast_interface() -> Option<Ipv6InterfaceInfo> {
-    match get_if_addrs() {
-        Ok(interfaces) => {
-            for iface in interfaces.iter() {
-                if let IpAddr::V6(ipv6_addr) = iface.addr.ip() {
-                    if ipv6_addr.is_loopback() || ipv6_addr.is_unspecified() {
-                        continue;
-                    }
+    let interfaces = match get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            error!(
+                "[IPv6] Failed to get network interfaces: {}. Specify the interface by name to skip enumeration.",
+                e
+            );
+            return None;
+        }
+    };
 
-                    if !ipv6_addr.is_unicast_link_local() {
-                        if let Ok(index) = get_interface_index(&iface.name) {
-                            return Some(Ipv6InterfaceInfo {
-                                index,
-                                name: iface.name.clone(),
-                            });
-                        }
-                    }
-                }
-            }
+    let preference = interface_preference();
+    let mut best: Option<((usize, bool, bool), Ipv6InterfaceInfo, Ipv6Addr)> = None;
+    for iface in interfaces.iter() {
+        let IpAddr::V6(ipv6_addr) = iface.addr.ip() else {
+            continue;
+        };
+        if ipv6_addr.is_loopback() || ipv6_addr.is_unspecified() {
+            continue;
+        }
+        let Ok(index) = get_interface_index(&iface.name) else {
+            continue;
+        };
 
-            for iface in interfaces.iter() {
-                if let IpAddr::V6(ipv6_addr) = iface.addr.ip() {
-                    if ipv6_addr.is_loopback() || ipv6_addr.is_unspecified() {
-                        continue;
-                    }
+        let rank = (
+            preference.name_rank(&iface.name),
+            preference.prefer_physical && is_virtual_interface(&iface.name),
+            ipv6_addr.is_unicast_link_local(),
+        );
+        debug!("[IPv6] Candidate {} ({}), rank {:?}", iface.name, ipv6_addr, rank);
+        if best.as_ref().is_none_or(|(best_rank, _, _)| rank < *best_rank) {
+            best = Some((rank, Ipv6InterfaceInfo { index, name: iface.name.clone() }, ipv6_addr));
+        }
+    }
 
-                    if let Ok(index) = get_interface_index(&iface.name) {
-                        return Some(Ipv6InterfaceInfo {
-                            index,
-                            name: iface.name.clone(),
-                        });
-                    }
-                }
-            }
+    let Some(((_, is_virtual, link_local), info, ipv6_addr)) = best else {
+        error!("[IPv6] No suitable IPv6 interface found");
+        return None;
+    };
+    if link_local {
+        info!(
+            "[IPv6] No global IPv6 address found, using link-local {} on {} (scope id {})",
+            ipv6_addr, info.name, info.index
+        );
+    }
+    if is_virtual {
+        warn!("[IPv6] Only virtual-looking interfaces are available, using {}", info.name);
+    }
+    Some(info)
+}
 
-            error!("[IPv6] No suitable IPv6 interface found");
-            None
-        }
-        Err(e) => {
-            error!("[IPv6] Failed to get network interfaces: {}", e);
-            None
+/// Attaches the interface index as scope id to an IPv6 address, which link-local
+/// groups (`ff02::/16`) and link-local peers need to be routable at all.
+pub fn with_ipv6_scope(addr: SocketAddr, interface_index: u32) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) if interface_index != 0 => {
+            SocketAddr::V6(SocketAddrV6::new(*v6.ip(), v6.port(), v6.flowinfo(), interface_index))
         }
+        other => other,
     }
 }
 
@@ -272,31 +771,58 @@ pub struct Message {
     pub length: u16,
     pub uuid: String,
     pub text: String,
+    /// The sender's `max_message_size`, sent after the payload where receivers
+    /// that don't know about it ignore it. `None` from older senders.
+    pub advertised_max_size: Option<u16>,
 }
 
 impl Message {
     pub fn serialize(&self) -> io::Result<Vec<u8>> {
+        self.serialize_with(false)
+    }
+
+    /// With `compress`, the payload is sent deflated if that makes it smaller;
+    /// tiny payloads go out as they are.
+    pub fn serialize_with(&self, compress: bool) -> io::Result<Vec<u8>> {
+        self.serialize_within(compress, MAX_MESSAGE_SIZE)
+    }
+
+    /// Same as [`Message::serialize_with`], refusing payloads over `max_size`
+    /// bytes instead of the default `MAX_MESSAGE_SIZE`.
+    pub fn serialize_within(&self, compress: bool, max_size: usize) -> io::Result<Vec<u8>> {
         let mut buffer = Vec::new();
         
-        buffer.push(self.msg_type);
-        
-        let text_bytes = self.text.as_bytes();
-        let uuid_bytes = self.uuid.as_bytes();
-        
-        let total_length = uuid_bytes.len() + text_bytes.len();
+        let mut payload = Vec::with_capacity(self.uuid.len() + self.text.len());
+        payload.extend_from_slice(self.uuid.as_bytes());
+        payload.extend_from_slice(self.text.as_bytes());
+
+        let mut msg_type = self.msg_type;
+        if compress && payload.len() <= MAX_DECOMPRESSED_MESSAGE_SIZE {
+            let compressed = miniz_oxide::deflate::compress_to_vec(&payload, 6);
+            if compressed.len() < payload.len() {
+                payload = compressed;
+                msg_type |= MSG_FLAG_COMPRESSED;
+            }
+        }
+
+        let total_length = payload.len();
         
-        if total_length > MAX_MESSAGE_SIZE {
+        if total_length > max_size {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                format!("Message too long: {} bytes (max {})", total_length, MAX_MESSAGE_SIZE)
+                format!("Message too long: {} bytes (max {})", total_length, max_size)
             ));
         }
         
-        buffer.extend_from_slice(&(total_length as u16).to_be_bytes());
+        buffer.push(msg_type);
         
-        buffer.extend_from_slice(uuid_bytes);
+        buffer.extend_from_slice(&(total_length as u16).to_be_bytes());
         
-        buffer.extend_from_slice(text_bytes);
+        buffer.extend_from_slice(&payload);
+
+        if let Some(max_size) = self.advertised_max_size {
+            buffer.extend_from_slice(&max_size.to_be_bytes());
+        }
         
         Ok(buffer)
     }
@@ -309,31 +835,45 @@ impl Message {
             ));
         }
         
-        let msg_type = data[0];
+        let msg_type = data[0] & !MSG_FLAG_COMPRESSED;
         
-        let length = u16::from_be_bytes([data[1], data[2]]) as usize;
+        let wire_length = u16::from_be_bytes([data[1], data[2]]) as usize;
         
-        if data.len() < 3 + length {
+        if data.len() < 3 + wire_length {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                format!("Data too short: expected {} bytes, got {}", 3 + length, data.len())
+                format!("Data too short: expected {} bytes, got {}", 3 + wire_length, data.len())
             ));
         }
+
+        let wire_payload = &data[3..3 + wire_length];
+        let advertised_max_size = data
+            .get(3 + wire_length..3 + wire_length + 2)
+            .map(|trailer| u16::from_be_bytes([trailer[0], trailer[1]]));
+        let inflated;
+        let payload = if data[0] & MSG_FLAG_COMPRESSED != 0 {
+            inflated = miniz_oxide::inflate::decompress_to_vec_with_limit(wire_payload, MAX_DECOMPRESSED_MESSAGE_SIZE)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Bad compressed payload: {}", e)))?;
+            inflated.as_slice()
+        } else {
+            wire_payload
+        };
+        let length = payload.len();
         
         let uuid;
         let text;
         
         if length >= 36 {
-            uuid = String::from_utf8_lossy(&data[3..39]).to_string();
+            uuid = String::from_utf8_lossy(&payload[..36]).to_string();
             
             if length > 36 {
-                text = String::from_utf8_lossy(&data[39..3 + length]).to_string();
+                text = String::from_utf8_lossy(&payload[36..]).to_string();
             } else {
                 text = String::new();
             }
         } else {
             uuid = String::new();
-            text = String::from_utf8_lossy(&data[3..3 + length]).to_string();
+            text = String::from_utf8_lossy(payload).to_string();
         }
 
         Ok(Message {
@@ -341,6 +881,7 @@ impl Message {
             length: length as u16,
             uuid,
             text,
+            advertised_max_size,
         })
     }
 }
@@ -362,6 +903,7 @@ pub fn new_socket(addr: &SocketAddr) -> io::Result<Socket> {
 pub fn join_multicast(addr: SocketAddr, interface_name: Option<&str>) -> io::Result<Socket> {
     let ip_addr = addr.ip();
     let socket = new_socket(&addr)?;
+    let mut bind_addr = addr;
 
     match ip_addr {
         IpAddr::V4(ref mdns_v4) => {
@@ -385,18 +927,43 @@ pub fn join_multicast(addr: SocketAddr, interface_name: Option<&str>) -> io::Res
             if let Some(info) = interface_info {
                 bind_socket_to_ipv6_interface(&socket, info.index)?;
             }
+
+            bind_addr = with_ipv6_scope(addr, interface_index);
         }
     };
 
-    socket.bind(&SockAddr::from(addr))?;
+    socket.bind(&SockAddr::from(bind_addr))?;
     Ok(socket)
 }
 
 pub fn create_sender(addr: &SocketAddr, interface_name: Option<&str>) -> io::Result<Socket> {
+    let config = MulticastConfig {
+        ip: addr.ip(),
+        port: addr.port(),
+        interface_name: interface_name.map(str::to_string),
+        ..MulticastConfig::default()
+    };
+    create_scoped_sender(&config).map(|(socket, _)| socket)
+}
+
+/// Same as [`create_sender`], but takes TTL and loopback from `config` (the same
+/// way for both address families) and also returns the destination to send to,
+/// with the chosen interface applied as IPv6 scope id.
+pub fn create_scoped_sender(config: &MulticastConfig) -> io::Result<(Socket, SocketAddr)> {
+    let addr = &SocketAddr::new(config.ip, config.port);
+    let interface_name = config.interface_name.as_deref();
+    let ttl = config.multicast_ttl;
+    let loopback = config.loopback;
+
+    check_multicast_ttl(config)?;
+
     let socket = new_socket(addr)?;
+    let mut destination = *addr;
     
     if addr.is_ipv4() {
         socket.set_multicast_if_v4(&Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_ttl_v4(ttl)?;
+        socket.set_multicast_loop_v4(loopback)?;
         socket.bind(&SockAddr::from(SocketAddr::new(
             Ipv4Addr::UNSPECIFIED.into(),
             0,
@@ -412,51 +979,265 @@ pub fn create_sender(addr: &SocketAddr, interface_name: Option<&str>) -> io::Res
             );
             socket.set_multicast_if_v6(info.index)?;
             bind_socket_to_ipv6_interface(&socket, info.index)?;
+            destination = with_ipv6_scope(*addr, info.index);
         } else {
             error!("[IPv6] No suitable interface found for multicast - sending may fail");
         }
         
-        socket.set_multicast_loop_v6(true)?;
+        socket.set_multicast_hops_v6(ttl)?;
+        socket.set_multicast_loop_v6(loopback)?;
         socket.bind(&SockAddr::from(SocketAddr::new(
             Ipv6Addr::UNSPECIFIED.into(),
             0,
         )))?;
     }
     
-    Ok(socket)
+    Ok((socket, destination))
+}
+
+fn check_multicast_ttl(config: &MulticastConfig) -> io::Result<()> {
+    let ttl = config.multicast_ttl;
+    if ttl > MAX_SAFE_MULTICAST_TTL {
+        if !config.allow_wide_scope {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Multicast TTL {} exceeds the safe limit of {}; enable allow_wide_scope to send beyond the local site",
+                    ttl, MAX_SAFE_MULTICAST_TTL
+                ),
+            ));
+        }
+        warn!("[CLIENT] Multicast TTL {} may flood routers well beyond the local site", ttl);
+    }
+    Ok(())
 }
 
-pub fn server_thread(stop_flag: Arc<AtomicBool>, instance_id: String, config: MulticastConfig) {
-    let mcast_addr = SocketAddr::new(config.ip, config.port);
-    let protocol = if config.is_ipv4() { "IPv4" } else { "IPv6" };
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JoinState {
+    Starting,
+    Joined,
+    Failed(String),
+}
 
-    info!("[SERVER] Starting multicast listener on {}:{} ({})", config.ip, config.port, protocol);
-    info!("[SERVER] Instance ID: {}", instance_id);
+/// Whether a server thread managed to join its multicast group, shared between
+/// the thread and whoever started it.
+#[derive(Clone)]
+pub struct ServerReadiness {
+    state: Arc<(Mutex<JoinState>, Condvar)>,
+}
 
-    let listener = match join_multicast(mcast_addr, config.interface_name.as_deref()) {
-        Ok(sock) => sock,
-        Err(e) => {
-            error!("[SERVER] Failed to join multicast group: {}", e);
-            return;
+impl Default for ServerReadiness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerReadiness {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new((Mutex::new(JoinState::Starting), Condvar::new())),
         }
-    };
-    
-    info!("[SERVER] Successfully joined multicast group, waiting for messages...");
-    
-    let cleanup_flag = Arc::clone(&stop_flag);
-    thread::spawn(move || {
-        while !cleanup_flag.load(Ordering::Relaxed) {
-            thread::sleep(Duration::from_secs(2));
-            let removed = cleanup_inactive_devices(Duration::from_secs(14));
+    }
+
+    pub fn state(&self) -> JoinState {
+        self.state.0.lock().unwrap().clone()
+    }
+
+    pub fn is_joined(&self) -> bool {
+        self.state() == JoinState::Joined
+    }
+
+    pub fn mark_joined(&self) {
+        self.set(JoinState::Joined);
+    }
+
+    pub fn mark_failed(&self, error: &io::Error) {
+        self.set(JoinState::Failed(error.to_string()));
+    }
+
+    /// Blocks until the join succeeded or failed; returns `Starting` if neither
+    /// happened within `timeout`.
+    pub fn wait(&self, timeout: Duration) -> JoinState {
+        let (lock, ready) = &*self.state;
+        let state = lock.lock().unwrap();
+        let (state, _) = ready
+            .wait_timeout_while(state, timeout, |state| *state == JoinState::Starting)
+            .unwrap();
+        state.clone()
+    }
+
+    fn set(&self, new_state: JoinState) {
+        let (lock, ready) = &*self.state;
+        *lock.lock().unwrap() = new_state;
+        ready.notify_all();
+    }
+}
+
+/// A server started with [`spawn_server`].
+pub struct ServerHandle {
+    running: Arc<AtomicBool>,
+    readiness: ServerReadiness,
+    thread: JoinHandle<Option<JoinHandle<()>>>,
+}
+
+impl ServerHandle {
+    pub fn is_joined(&self) -> bool {
+        self.readiness.is_joined()
+    }
+
+    pub fn readiness(&self) -> &ServerReadiness {
+        &self.readiness
+    }
+
+    /// Waits for the join outcome, turning a failure or timeout into an error.
+    pub fn wait_for_join(&self, timeout: Duration) -> io::Result<()> {
+        match self.readiness.wait(timeout) {
+            JoinState::Joined => Ok(()),
+            JoinState::Failed(e) => Err(io::Error::other(format!("Failed to join multicast group: {}", e))),
+            JoinState::Starting => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Multicast join did not finish within {:?}", timeout),
+            )),
+        }
+    }
+
+    pub fn stop(self) {
+        if let Some(cleanup) = self.shut_down() {
+            let _ = cleanup.join();
+        }
+    }
+
+    /// Stops the server and returns its device cleanup thread, which exits
+    /// within one 100ms check of the running flag.
+    fn shut_down(self) -> Option<JoinHandle<()>> {
+        stop_server(Arc::clone(&self.running));
+        self.thread.join().ok().flatten()
+    }
+}
+
+/// Starts [`server_thread`] in the background and returns a handle to query
+/// whether the multicast join succeeded and to stop it later.
+pub fn spawn_server(instance_id: String, config: MulticastConfig) -> ServerHandle {
+    let running = Arc::new(AtomicBool::new(true));
+    let readiness = ServerReadiness::new();
+
+    let thread_flag = Arc::clone(&running);
+    let thread_readiness = readiness.clone();
+    let thread = thread::Builder::new()
+        .name("mcast-server".to_string())
+        .spawn(move || run_server(thread_flag, instance_id, config, &thread_readiness))
+        .expect("failed to spawn mcast-server thread");
+
+    ServerHandle { running, readiness, thread }
+}
+
+/// Listens until `running` is set to `false` (see [`stop_server`]). All loops in
+/// this crate use the same convention: `true` while running, `false` to stop.
+pub fn server_thread(running: Arc<AtomicBool>, instance_id: String, config: MulticastConfig) {
+    if let Some(cleanup) = run_server(running, instance_id, config, &ServerReadiness::new()) {
+        let _ = cleanup.join();
+    }
+}
+
+/// Periodically drops devices silent for `timeout`, until `running` goes false. Checks the flag every 100ms so stopping is never held up by the
+/// 2s cleanup interval; join the handle to be sure the thread is gone.
+pub fn spawn_device_cleanup(running: Arc<AtomicBool>, timeout: Duration) -> JoinHandle<()> {
+    let spawned = thread::Builder::new().name("mcast-cleanup".to_string()).spawn(move || {
+        let mut last_cleanup = Instant::now();
+        while running.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(100));
+            if last_cleanup.elapsed() < Duration::from_secs(2) {
+                continue;
+            }
+            last_cleanup = Instant::now();
+
+            let removed = cleanup_inactive_devices(timeout);
             if !removed.is_empty() {
                 info!("[CLEANUP] Removed {} inactive device(s)", removed.len());
             }
         }
     });
+    spawned.expect("failed to spawn mcast-cleanup thread")
+}
+
+/// Returns the device cleanup thread, if the join got far enough to start one,
+/// for the caller to join.
+fn run_server(
+    running: Arc<AtomicBool>,
+    instance_id: String,
+    config: MulticastConfig,
+    readiness: &ServerReadiness,
+) -> Option<JoinHandle<()>> {
+    for group in config.groups() {
+        let protocol = if group.is_ipv4() { "IPv4" } else { "IPv6" };
+        info!("[SERVER] Starting multicast listener on {}:{} ({})", group, config.port, protocol);
+    }
+    info!("[SERVER] Instance ID: {}", instance_id);
+    info!(
+        "[SERVER] Device timeout {:?}, heartbeat interval {:?} (jitter ±{:.0}%)",
+        config.device_timeout,
+        config.heartbeat_interval,
+        config.heartbeat_jitter * 100.0
+    );
+
+    let mut listeners = Vec::new();
+    for group in config.groups() {
+        match join_multicast(SocketAddr::new(group, config.port), config.interface_name.as_deref()) {
+            Ok(sock) => listeners.push((group, sock)),
+            Err(e) => {
+                error!("[SERVER] Failed to join multicast group {}: {}", group, e);
+                readiness.mark_failed(&e);
+                return None;
+            }
+        }
+    }
     
-    let mut buf = [MaybeUninit::<u8>::uninit(); 1024];
+    info!("[SERVER] Successfully joined multicast group, waiting for messages...");
+    readiness.mark_joined();
+    
+    let cleanup = spawn_device_cleanup(Arc::clone(&running), config.device_timeout);
+
+    // The first group is served on this thread, any dual-stack one on its own.
+    let mut listeners = listeners.into_iter();
+    let (_, first_listener) = listeners.next().expect("config has at least one group");
+    let extra_receivers: Vec<JoinHandle<()>> = listeners
+        .map(|(group, listener)| {
+            let running = Arc::clone(&running);
+            let instance_id = instance_id.clone();
+            let config = config.clone();
+            let name = if group.is_ipv6() { "mcast-server-v6" } else { "mcast-server-v4" };
+            thread::Builder::new()
+                .name(name.to_string())
+                .spawn(move || receive_messages(&running, &instance_id, &config, &listener, log_received))
+                .expect("failed to spawn multicast receive thread")
+        })
+        .collect();
+
+    receive_messages(&running, &instance_id, &config, &first_listener, log_received);
+
+    for receiver in extra_receivers {
+        let _ = receiver.join();
+    }
+    ACTIVE_DEVICES.lock().unwrap().clear();
+    info!("[SERVER] Shutting down");
+    Some(cleanup)
+}
+
+/// Receives on one joined group until `running` goes false, feeding the shared
+/// `ACTIVE_DEVICES`. Each message that gets through (not our own, not a
+/// rate-limited heartbeat, not a query for another device) is then handed to
+/// `on_message` with its type name and sender.
+pub fn receive_messages<F>(running: &AtomicBool, instance_id: &str, config: &MulticastConfig, listener: &Socket, mut on_message: F)
+where
+    F: FnMut(Message, &'static str, Option<SocketAddr>),
+{
+    // Large enough for any peer's `max_message_size`, so a bigger one is
+    // flagged rather than cut off.
+    let mut buf = vec![MaybeUninit::<u8>::uninit(); u16::MAX as usize + 1];
+    let mut rate_limiter = MessageRateLimiter::new(config.min_message_interval);
+    let mut peer_max_sizes: HashMap<String, u16> = HashMap::new();
     
-    while !stop_flag.load(Ordering::Relaxed) {
+    while running.load(Ordering::Relaxed) {
         match listener.recv_from(&mut buf) {
             Ok((len, remote_addr)) => {
                 let data = unsafe {
@@ -470,28 +1251,32 @@ pub fn server_thread(stop_flag: Arc<AtomicBool>, instance_id: String, config: Mu
                             continue;
                         }
                         
+                        if msg.msg_type == MSG_TYPE_HEARTBEAT && !rate_limiter.allow(&msg.uuid) {
+                            continue;
+                        }
+
                         let msg_type_str = match msg.msg_type {
                             MSG_TYPE_HEARTBEAT => {
+                                check_peer_max_size(&mut peer_max_sizes, &msg, config.max_message_size);
                                 update_device(msg.uuid.clone(), msg.text.clone());
                                 "HEARTBEAT"
                             },
+                            MSG_TYPE_QUERY => {
+                                if !handle_query(&msg, instance_id) {
+                                    continue;
+                                }
+                                "QUERY"
+                            },
                             MSG_TYPE_DISCONNECT => {
+                                rate_limiter.forget(&msg.uuid);
+                                peer_max_sizes.remove(&msg.uuid);
                                 remove_device(&msg.uuid);
                                 "DISCONNECT"
                             },
                             _ => "UNKNOWN",
                         };
 
-                        let device_count = get_active_device_count();
-
-                        info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
-                        info!("[SERVER] Received message from {:?}", remote_socket);
-                        info!("Type: {} ({})", msg_type_str, msg.msg_type);
-                        info!("Length: {} bytes", msg.length);
-                        info!("UUID: {}", msg.uuid);
-                        info!("Text: {}", msg.text);
-                        info!("Active devices: {}", device_count);
-                        info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+                        on_message(msg, msg_type_str, remote_socket);
                     }
                     Err(e) => {
                         error!("[SERVER] Failed to deserialize message: {}", e);
@@ -506,43 +1291,90 @@ pub fn server_thread(stop_flag: Arc<AtomicBool>, instance_id: String, config: Mu
             }
         }
     }
+}
 
-    ACTIVE_DEVICES.lock().unwrap().clear();
-    info!("[SERVER] Shutting down");
+fn log_received(msg: Message, msg_type_str: &str, remote_socket: Option<SocketAddr>) {
+    let device_count = get_active_device_count();
+
+    info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+    info!("[SERVER] Received message from {:?}", remote_socket);
+    info!("Type: {} ({})", msg_type_str, msg.msg_type);
+    info!("Length: {} bytes", msg.length);
+    info!("UUID: {}", msg.uuid);
+    info!("Text: {}", msg.text);
+    info!("Active devices: {}", device_count);
+    info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+}
+
+/// Warns once per peer (and again if it changes) when a heartbeat announces a
+/// `max_message_size` other than ours: larger messages from it may be lost on
+/// links sized for ours, and older receivers truncate anything over 1024 bytes.
+fn check_peer_max_size(known: &mut HashMap<String, u16>, msg: &Message, own_max: usize) {
+    let Some(peer_max) = msg.advertised_max_size else {
+        return;
+    };
+    if known.insert(msg.uuid.clone(), peer_max) == Some(peer_max) || peer_max as usize == own_max {
+        return;
+    }
+    warn!(
+        "[SERVER] Device {} is configured for messages up to {} bytes, this node for {}",
+        msg.uuid, peer_max, own_max
+    );
 }
 
-pub fn stop_server(server_stop_flag: Arc<AtomicBool>) {
+pub fn stop_server(server_running: Arc<AtomicBool>) {
     info!("[STOP SERVER] Stopping server...");
-    server_stop_flag.store(true, Ordering::Relaxed);
+    server_running.store(false, Ordering::Relaxed);
 }
 
-pub fn client_thread(stop_flag: Arc<AtomicBool>, instance_id: String, config: MulticastConfig) {
-    let mcast_addr = SocketAddr::new(config.ip, config.port);
-    let protocol = if config.is_ipv4() { "IPv4" } else { "IPv6" };
+/// Sends heartbeats until `running` is set to `false` (see [`disconnect`]).
+pub fn client_thread(
+    running: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    instance_id: String,
+    config: MulticastConfig,
+) {
+    let protocol = match config.dual_stack_ip {
+        Some(_) => "IPv4 and IPv6",
+        None if config.is_ipv4() => "IPv4",
+        None => "IPv6",
+    };
     
     thread::sleep(Duration::from_millis(500));
 
     info!("[CLIENT] Starting multicast sender ({})", protocol);
     
-    let interface_ref = config.interface_name.as_deref();
-
-    let sender = match create_sender(&mcast_addr, interface_ref) {
-        Ok(sock) => sock,
+    let mut sender = match create_multicast_sender(&config) {
+        Ok(sender) => sender,
         Err(e) => {
             error!("[CLIENT] Failed to create sender socket: {}", e);
             return;
         }
     };
+    if config.send_on_all_interfaces {
+        info!("[CLIENT] Announcing on all interfaces: {}", sender.interfaces().join(", "));
+    }
     
-    let sock_addr = SockAddr::from(mcast_addr);
     let mut counter = 0;
     
     *MESSAGE_TEXT.lock().unwrap() = config.message.clone();
     
-    info!("[CLIENT] Sending messages to {}:{} every 3 seconds...", config.ip, config.port);
+    let destinations: Vec<String> = config.groups().iter().map(|group| format!("{}:{}", group, config.port)).collect();
+    info!(
+        "[CLIENT] Sending messages to {} every {:?} (jitter ±{:.0}%)...",
+        destinations.join(" and "),
+        config.heartbeat_interval,
+        config.heartbeat_jitter * 100.0
+    );
+
+    while running.load(Ordering::Relaxed) {
+        if paused.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
 
-    while !stop_flag.load(Ordering::Relaxed) {
         counter += 1;
+        let last_sent = Instant::now();
         
         let msg_type = MSG_TYPE_HEARTBEAT;
         let text = MESSAGE_TEXT.lock().unwrap().clone();
@@ -552,15 +1384,17 @@ pub fn client_thread(stop_flag: Arc<AtomicBool>, instance_id: String, config: Mu
             length: text.len() as u16,
             uuid: instance_id.clone(),
             text: format!("{} #{}", text, counter),
+            advertised_max_size: Some(config.max_message_size as u16),
         };
         
-        match message.serialize() {
+        match message.serialize_within(config.compress_payloads, config.max_message_size) {
             Ok(data) => {
-                match sender.send_to(&data, &sock_addr) {
+                match sender.send(&data) {
                     Ok(bytes_sent) => {
                         let msg_type_str = match msg_type {
                             0 => "HEARTBEAT",
                             1 => "DISCONNECT",
+                            2 => "QUERY",
                             _ => "UNKNOWN",
                         };
                         info!("[CLIENT] Sent {} bytes (type: {}): {}", bytes_sent, msg_type_str, message.text);
@@ -575,20 +1409,24 @@ pub fn client_thread(stop_flag: Arc<AtomicBool>, instance_id: String, config: Mu
             }
         }
         
-        for _ in 0..30 {
-            if stop_flag.load(Ordering::Relaxed) {
+        let ticks = (config.next_heartbeat_delay().as_millis() / 100).max(1);
+        for _ in 0..ticks {
+            if !running.load(Ordering::Relaxed)
+                || paused.load(Ordering::Relaxed)
+                || take_heartbeat_request(last_sent, config.min_message_interval)
+            {
                 break;
             }
             thread::sleep(Duration::from_millis(100));
         }
     }
     
-    send_disconnect_message(&sender, &sock_addr, &instance_id);
+    sender.send_disconnect(&instance_id);
 
     info!("[CLIENT] Shutting down");
 }
 
-pub fn send_disconnect_message(sender: &Socket, sock_addr: &SockAddr, instance_id: &str) {
+pub fn send_disconnect_message(sender: &Socket, sock_addr: &SockAddr, instance_id: &str, compress: bool, max_size: usize) {
     let text = MESSAGE_TEXT.lock().unwrap().clone();
     
     let disconnect_msg = Message {
@@ -596,9 +1434,10 @@ pub fn send_disconnect_message(sender: &Socket, sock_addr: &SockAddr, instance_i
         length: text.len() as u16,
         uuid: instance_id.to_string(),
         text: format!("{} - Disconnecting", text),
+        advertised_max_size: None,
     };
     
-    match disconnect_msg.serialize() {
+    match disconnect_msg.serialize_within(compress, max_size) {
         Ok(data) => {
             match sender.send_to(&data, sock_addr) {
                 Ok(bytes_sent) => {
@@ -615,7 +1454,75 @@ pub fn send_disconnect_message(sender: &Socket, sock_addr: &SockAddr, instance_i
     }
 }
 
-pub fn disconnect(client_stop_flag: Arc<AtomicBool>) {
+/// Sends a `MSG_TYPE_QUERY` asking `target_uuid` to report in; pair with
+/// [`wait_for_device_update`] to see whether it answered.
+pub fn send_query(sender: &Socket, sock_addr: &SockAddr, instance_id: &str, target_uuid: &str) -> io::Result<usize> {
+    let query = Message {
+        msg_type: MSG_TYPE_QUERY,
+        length: target_uuid.len() as u16,
+        uuid: instance_id.to_string(),
+        text: target_uuid.to_string(),
+        advertised_max_size: None,
+    };
+
+    let bytes_sent = sender.send_to(&query.serialize()?, sock_addr)?;
+    info!("[CLIENT] Sent QUERY for {} ({} bytes)", target_uuid, bytes_sent);
+    Ok(bytes_sent)
+}
+
+pub fn pause_client(client_paused: Arc<AtomicBool>) {
+    info!("[PAUSE] Pausing client, socket stays open...");
+    client_paused.store(true, Ordering::Relaxed);
+}
+
+pub fn resume_client(client_paused: Arc<AtomicBool>) {
+    info!("[RESUME] Resuming client...");
+    client_paused.store(false, Ordering::Relaxed);
+}
+
+pub fn disconnect(client_running: Arc<AtomicBool>) {
     info!("[DISCONNECT] Stopping client and sending disconnect message...");
-    client_stop_flag.store(true, Ordering::Relaxed);
+    client_running.store(false, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stopping_a_server_ends_its_cleanup_thread() {
+        let config = MulticastConfig { port: 48_672, ..MulticastConfig::default() };
+        for _ in 0..5 {
+            let server = spawn_server(generate_instance_id(), config.clone());
+            server.wait_for_join(Duration::from_secs(2)).unwrap();
+
+            let cleanup = server.shut_down().expect("a joined server runs a cleanup thread");
+            let deadline = Instant::now() + Duration::from_secs(1);
+            while !cleanup.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert!(cleanup.is_finished());
+            cleanup.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn corrupted_frame_creates_no_device() {
+        let heartbeat = Message {
+            msg_type: MSG_TYPE_HEARTBEAT,
+            length: 0,
+            uuid: generate_instance_id(),
+            text: "Hello from client #1".to_string(),
+            advertised_max_size: None,
+        };
+        let mut frame = heartbeat.serialize().unwrap();
+        // Two bytes inside the UUID, past the 3-byte header, flipped in transit.
+        frame[3 + 4] ^= 0x80;
+        frame[3 + 8] = b'x';
+
+        let received = Message::deserialize(&frame).unwrap();
+        assert_ne!(received.uuid, heartbeat.uuid);
+        update_device(received.uuid, received.text);
+        assert_eq!(get_active_device_count(), 0);
+    }
 }
@@ -3,9 +3,11 @@ use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
-use log::{LevelFilter, info};
+use log::{LevelFilter, info, error};
 use clap::Parser;
 
+const SERVER_JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Parser, Debug)]
 #[command(name = "multicast")]
 #[command(about = "Multicast UDP messenger", long_about = None)]
@@ -16,6 +18,11 @@ struct Args {
     #[arg(short, long, default_value_t = 8888)]
     port: u16,
 
+    /// Also announce and listen on this group of the other address family
+    /// (e.g. ff02::fb next to an IPv4 --ip), on the same port
+    #[arg(long)]
+    dual_stack_ip: Option<String>,
+
     #[arg(short, long, default_value = "Hello from client")]
     message: String,
 
@@ -24,6 +31,88 @@ struct Args {
 
     #[arg(short = 'I', long)]
     interface: Option<String>,
+
+    /// Interface to prefer when auto-detecting (repeatable, in order; `wl*` matches a prefix)
+    #[arg(long = "prefer-interface")]
+    prefer_interfaces: Vec<String>,
+
+    /// Don't rank virtual interfaces (Docker, VPN, bridges) below physical ones
+    #[arg(long)]
+    allow_virtual_interfaces: bool,
+
+    /// Send heartbeats out of every suitable interface, not just the selected one
+    #[arg(long)]
+    all_interfaces: bool,
+
+    #[arg(long, default_value_t = 500)]
+    min_interval_ms: u64,
+
+    #[arg(long, default_value_t = DEFAULT_MULTICAST_TTL)]
+    ttl: u32,
+
+    #[arg(long)]
+    allow_wide_scope: bool,
+
+    #[arg(long)]
+    no_loopback: bool,
+
+    #[arg(long, default_value_t = 3000)]
+    heartbeat_ms: u64,
+
+    #[arg(long, default_value_t = 0.0)]
+    jitter: f64,
+
+    /// Drop devices that have been silent this long
+    #[arg(long, default_value_t = 14000)]
+    device_timeout_ms: u64,
+
+    #[arg(long)]
+    instance_id: Option<String>,
+
+    /// Deflate heartbeat payloads when that makes them smaller
+    #[arg(long)]
+    compress: bool,
+
+    /// Largest heartbeat payload to send; above the path MTU (about 1472 bytes on
+    /// plain Ethernet) datagrams get fragmented
+    #[arg(long, default_value_t = MAX_MESSAGE_SIZE)]
+    max_message_size: usize,
+
+    /// Join the group once, print a membership report and exit
+    #[arg(long)]
+    diagnose: bool,
+
+    /// Only watch the group: track devices without announcing this node
+    #[arg(long, conflicts_with = "announce_only")]
+    listen_only: bool,
+
+    /// Only announce this node, without listening for other devices
+    #[arg(long)]
+    announce_only: bool,
+}
+
+/// One block saying what is running and how it is configured, logged before the
+/// server and client threads start.
+fn print_startup_banner(config: &MulticastConfig, instance_id: &str, mode: &str, duration_secs: u64) {
+    info!("=== multicast {} ===", env!("CARGO_PKG_VERSION"));
+    info!("  mode:           {}", mode);
+    for group in config.groups() {
+        info!("  group:          {}:{} ({})", group, config.port, if group.is_ipv6() { "IPv6" } else { "IPv4" });
+    }
+    info!("  interface:      {}", config.interface_name.as_deref().unwrap_or("auto"));
+    info!("  send on:        {}", if config.send_on_all_interfaces { "all interfaces" } else { "selected interface" });
+    info!("  instance id:    {}", instance_id);
+    info!(
+        "  heartbeat:      {:?} (jitter ±{:.0}%)",
+        config.heartbeat_interval,
+        config.heartbeat_jitter * 100.0
+    );
+    info!("  device timeout: {:?}", config.device_timeout);
+    info!("  ttl:            {}{}", config.multicast_ttl, if config.allow_wide_scope { " (wide scope allowed)" } else { "" });
+    info!("  loopback:       {}", if config.loopback { "on" } else { "off" });
+    info!("  compression:    {}", if config.compress_payloads { "on" } else { "off" });
+    info!("  max message:    {} bytes", config.max_message_size);
+    info!("  run time:       {}s", duration_secs);
 }
 
 fn main() {
@@ -39,35 +128,102 @@ fn main() {
         args.port, 
         args.message,
         args.interface.clone()
-    ).expect("Invalid IP address");
-
-    let instance_id = generate_instance_id();
-
-    let server_running = Arc::new(AtomicBool::new(false));
-    let client_running = Arc::new(AtomicBool::new(false));
-
-    let server_flag = Arc::clone(&server_running);
-    let server_id = instance_id.clone();
-    let server_config = config.clone();
-    let server_handle = thread::spawn(move || {
-        server_thread(server_flag, server_id, server_config);
+    )
+    .expect("Invalid IP address")
+    .with_min_message_interval(Duration::from_millis(args.min_interval_ms))
+    .with_multicast_ttl(args.ttl)
+    .with_allow_wide_scope(args.allow_wide_scope)
+    .with_loopback(!args.no_loopback)
+    .with_heartbeat_interval(Duration::from_millis(args.heartbeat_ms))
+    .with_heartbeat_jitter(args.jitter)
+    .with_device_timeout(Duration::from_millis(args.device_timeout_ms))
+    .with_compress_payloads(args.compress)
+    .with_send_on_all_interfaces(args.all_interfaces)
+    .with_max_message_size(args.max_message_size)
+    .with_dual_stack_ip(args.dual_stack_ip.as_deref().map(|ip| ip.parse().expect("Invalid dual-stack IP address")));
+
+    let config = match args.instance_id.as_deref() {
+        Some(id) => config.with_instance_id(id).expect("Invalid instance id"),
+        None => config,
+    };
+
+    set_interface_preference(InterfacePreference {
+        names: args.prefer_interfaces.clone(),
+        prefer_physical: !args.allow_virtual_interfaces,
     });
 
-    let client_flag = Arc::clone(&client_running);
-    let client_id = instance_id.clone();
-    let client_config = config.clone();
-    let client_handle = thread::spawn(move || {
-        client_thread(client_flag, client_id, client_config);
-    });
+    if let Err(e) = config.validate() {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+
+    if args.diagnose {
+        match diagnose_membership(&config) {
+            Ok(report) => {
+                println!("{}", report);
+                std::process::exit(if report.is_healthy() { 0 } else { 1 });
+            }
+            Err(e) => {
+                error!("Diagnosis failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let instance_id = config.resolve_instance_id();
+    let mode = if args.listen_only {
+        "listen only"
+    } else if args.announce_only {
+        "announce only"
+    } else {
+        "listen and announce"
+    };
+    print_startup_banner(&config, &instance_id, mode, args.duration);
+
+    let client_running = Arc::new(AtomicBool::new(true));
+    let client_paused = Arc::new(AtomicBool::new(false));
+
+    // The server still drops messages carrying our own instance id, so a node
+    // that only listens never lists itself.
+    let server = if args.announce_only {
+        None
+    } else {
+        let server = spawn_server(instance_id.clone(), config.clone());
+        if let Err(e) = server.wait_for_join(SERVER_JOIN_TIMEOUT) {
+            error!("{}", e);
+            server.stop();
+            return;
+        }
+        Some(server)
+    };
+
+    let client_handle = if args.listen_only {
+        None
+    } else {
+        let client_flag = Arc::clone(&client_running);
+        let client_paused_flag = Arc::clone(&client_paused);
+        let client_id = instance_id.clone();
+        let client_config = config.clone();
+        let handle = thread::Builder::new()
+            .name("mcast-client".to_string())
+            .spawn(move || {
+                client_thread(client_flag, client_paused_flag, client_id, client_config);
+            })
+            .expect("failed to spawn mcast-client thread");
+        Some(handle)
+    };
 
     thread::sleep(Duration::from_secs(args.duration));
 
     info!("\n=== Stopping ===\n");
     disconnect(Arc::clone(&client_running));
-    stop_server(Arc::clone(&server_running));
+    if let Some(server) = server {
+        server.stop();
+    }
 
-    let _ = server_handle.join();
-    let _ = client_handle.join();
+    if let Some(client_handle) = client_handle {
+        let _ = client_handle.join();
+    }
 
     info!("Done!");
 }
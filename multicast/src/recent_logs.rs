@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// How many records [`get_recent_logs`] keeps; older ones are dropped.
+pub const RECENT_LOG_CAPACITY: usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub time: SystemTime,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+static RECENT_LOGS: Mutex<VecDeque<LogEntry>> = Mutex::new(VecDeque::new());
+
+struct RecentLogCapture {
+    level: LevelFilter,
+    inner: Option<Box<dyn Log>>,
+}
+
+impl Log for RecentLogCapture {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut logs = RECENT_LOGS.lock().unwrap();
+        if logs.len() >= RECENT_LOG_CAPACITY {
+            logs.pop_front();
+        }
+        logs.push_back(LogEntry {
+            time: SystemTime::now(),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        });
+        drop(logs);
+
+        if let Some(inner) = &self.inner {
+            inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(inner) = &self.inner {
+            inner.flush();
+        }
+    }
+}
+
+/// Installs the global logger so records up to `level` are kept for
+/// [`get_recent_logs`], and also passed on to `inner` when one is given (e.g. a
+/// console logger). Fails if another logger was installed first.
+pub fn install_log_capture(level: LevelFilter, inner: Option<Box<dyn Log>>) -> Result<(), SetLoggerError> {
+    log::set_boxed_logger(Box::new(RecentLogCapture { level, inner }))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// The last [`RECENT_LOG_CAPACITY`] captured records, oldest first.
+pub fn get_recent_logs() -> Vec<LogEntry> {
+    RECENT_LOGS.lock().unwrap().iter().cloned().collect()
+}
+
+pub fn clear_recent_logs() {
+    RECENT_LOGS.lock().unwrap().clear();
+}
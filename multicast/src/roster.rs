@@ -0,0 +1,105 @@
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+
+use crate::{parse_instance_id, DeviceInfo, ACTIVE_DEVICES};
+
+/// Writes the current `ACTIVE_DEVICES` to `path`, one device per line, so a
+/// restarted app can show them again right away with [`load_device_roster`].
+pub fn save_device_roster(path: &Path) -> io::Result<usize> {
+    let now = SystemTime::now();
+    let devices = ACTIVE_DEVICES.lock().unwrap();
+    let mut contents = String::new();
+    for device in devices.values() {
+        let last_seen = now - device.last_seen.elapsed();
+        let last_seen_ms = last_seen.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        contents.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            device.uuid,
+            last_seen_ms,
+            device.message_count,
+            escape(&device.last_message)
+        ));
+    }
+    let saved = devices.len();
+    drop(devices);
+
+    let staged = path.with_extension("tmp");
+    std::fs::write(&staged, contents)?;
+    std::fs::rename(&staged, path)?;
+    info!("[ROSTER] Saved {} device(s) to {:?}", saved, path);
+    Ok(saved)
+}
+
+/// Adds the devices saved at `path` as stale entries, keeping the time they were
+/// last heard from so they still expire after `timeout` unless they send a
+/// heartbeat first. Devices already known, already expired or unparseable are
+/// skipped; a missing file loads nothing.
+pub fn load_device_roster(path: &Path, timeout: Duration) -> io::Result<usize> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let now = SystemTime::now();
+    let mut devices = ACTIVE_DEVICES.lock().unwrap();
+    let mut loaded = 0;
+    for line in contents.lines() {
+        let Some(device) = parse_line(line, now) else {
+            warn!("[ROSTER] Skipping malformed roster line {:?}", line);
+            continue;
+        };
+        if !device.is_alive(timeout) || devices.contains_key(&device.uuid) {
+            continue;
+        }
+        devices.insert(device.uuid.clone(), device);
+        loaded += 1;
+    }
+    info!("[ROSTER] Restored {} stale device(s) from {:?}", loaded, path);
+    Ok(loaded)
+}
+
+fn parse_line(line: &str, now: SystemTime) -> Option<DeviceInfo> {
+    let mut fields = line.splitn(4, '\t');
+    let uuid = parse_instance_id(fields.next()?).ok()?;
+    let last_seen_ms: u64 = fields.next()?.parse().ok()?;
+    let message_count: u32 = fields.next()?.parse().ok()?;
+    let last_message = unescape(fields.next()?);
+
+    let last_seen = UNIX_EPOCH + Duration::from_millis(last_seen_ms);
+    let age = now.duration_since(last_seen).unwrap_or_default();
+    Some(DeviceInfo {
+        uuid,
+        last_seen: Instant::now().checked_sub(age)?,
+        last_message,
+        message_count,
+        stale: true,
+    })
+}
+
+/// Message text comes from the network and may contain the separators.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,212 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use if_addrs::get_if_addrs;
+use log::{error, info, warn};
+use socket2::{SockAddr, Socket};
+
+use crate::{
+    bind_socket_to_ipv6_interface, check_multicast_ttl, create_scoped_sender, get_interface_index,
+    interface_preference, is_virtual_interface, new_socket, send_disconnect_message, with_ipv6_scope,
+    MulticastConfig,
+};
+
+/// How often the interface list is re-read when sending on all interfaces.
+pub const INTERFACE_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What a per-interface socket is pinned to: the local address for IPv4, the
+/// interface index for IPv6.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum OutputInterface {
+    V4(Ipv4Addr),
+    V6(u32),
+}
+
+struct Output {
+    name: String,
+    interface: Option<OutputInterface>,
+    socket: Socket,
+    destination: SockAddr,
+}
+
+/// Where heartbeats go out: the one interface [`create_scoped_sender`] picks, or
+/// with [`MulticastConfig::send_on_all_interfaces`] one socket per suitable
+/// interface, so nodes on every connected LAN see this one. The interface list
+/// is re-read every [`INTERFACE_REFRESH_INTERVAL`] to follow interfaces that
+/// come and go. With a [`MulticastConfig::dual_stack_ip`] each group gets its
+/// own sockets.
+pub struct MulticastSender {
+    config: MulticastConfig,
+    outputs: Vec<Output>,
+    last_refresh: Instant,
+}
+
+pub fn create_multicast_sender(config: &MulticastConfig) -> io::Result<MulticastSender> {
+    if !config.send_on_all_interfaces {
+        let mut outputs = Vec::new();
+        for group in config.groups() {
+            let (socket, destination) = create_scoped_sender(&config.for_group(group))?;
+            let name = match (group.is_ipv6(), crate::selected_ipv6_interface()) {
+                (true, Some(info)) => info.name,
+                _ => String::from("default"),
+            };
+            outputs.push(Output { name, interface: None, socket, destination: SockAddr::from(destination) });
+        }
+        return Ok(MulticastSender {
+            config: config.clone(),
+            outputs,
+            last_refresh: Instant::now(),
+        });
+    }
+
+    check_multicast_ttl(config)?;
+    let mut sender = MulticastSender {
+        config: config.clone(),
+        outputs: Vec::new(),
+        last_refresh: Instant::now(),
+    };
+    sender.refresh_interfaces()?;
+    if sender.outputs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No multicast-capable interface found to send on",
+        ));
+    }
+    Ok(sender)
+}
+
+impl MulticastSender {
+    /// Names of the interfaces currently sent on.
+    pub fn interfaces(&self) -> Vec<String> {
+        self.outputs.iter().map(|output| output.name.clone()).collect()
+    }
+
+    /// Sends `data` out of every interface. Succeeds with the datagram size if at
+    /// least one interface took it; failures on the others are logged.
+    pub fn send(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.config.send_on_all_interfaces
+            && self.last_refresh.elapsed() >= INTERFACE_REFRESH_INTERVAL
+            && let Err(e) = self.refresh_interfaces()
+        {
+            error!("[CLIENT] Failed to re-read network interfaces: {}", e);
+        }
+
+        let mut sent = None;
+        let mut last_error = None;
+        for output in &self.outputs {
+            match output.socket.send_to(data, &output.destination) {
+                Ok(bytes_sent) => sent = Some(bytes_sent),
+                Err(e) => {
+                    if self.outputs.len() > 1 {
+                        warn!("[CLIENT] Failed to send on {}: {}", output.name, e);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match (sent, last_error) {
+            (Some(bytes_sent), _) => Ok(bytes_sent),
+            (None, Some(e)) => Err(e),
+            (None, None) => Err(io::Error::new(io::ErrorKind::NotConnected, "No interface to send on")),
+        }
+    }
+
+    pub fn send_disconnect(&self, instance_id: &str) {
+        for output in &self.outputs {
+            send_disconnect_message(
+                &output.socket,
+                &output.destination,
+                instance_id,
+                self.config.compress_payloads,
+                self.config.max_message_size,
+            );
+        }
+    }
+
+    /// Opens sockets for interfaces that appeared and drops those of interfaces
+    /// that went away. An enumeration failure keeps the current set.
+    fn refresh_interfaces(&mut self) -> io::Result<()> {
+        self.last_refresh = Instant::now();
+        let mut found = Vec::new();
+        for group in self.config.groups() {
+            let interfaces = suitable_interfaces(group.is_ipv6())?;
+            found.extend(interfaces.into_iter().map(|(name, interface)| (group, name, interface)));
+        }
+
+        self.outputs.retain(|output| {
+            let present = found.iter().any(|(_, _, interface)| output.interface.as_ref() == Some(interface));
+            if !present {
+                info!("[CLIENT] Interface {} went away, no longer sending on it", output.name);
+            }
+            present
+        });
+
+        for (group, name, interface) in found {
+            if self.outputs.iter().any(|output| output.interface.as_ref() == Some(&interface)) {
+                continue;
+            }
+            match open_output(&self.config, group, &interface) {
+                Ok((socket, destination)) => {
+                    info!("[CLIENT] Sending on interface {} ({:?})", name, interface);
+                    self.outputs.push(Output { name, interface: Some(interface), socket, destination });
+                }
+                Err(e) => warn!("[CLIENT] Can't send on interface {}: {}", name, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Non-loopback interfaces with an address of the group's family, one entry per
+/// IPv4 address or IPv6 interface. Virtual-looking interfaces are left out while
+/// the [`crate::InterfacePreference`] prefers physical ones and any physical one
+/// is up.
+fn suitable_interfaces(ipv6: bool) -> io::Result<Vec<(String, OutputInterface)>> {
+    let mut found: Vec<(String, OutputInterface)> = Vec::new();
+    for iface in get_if_addrs()? {
+        if iface.is_loopback() {
+            continue;
+        }
+        let interface = match iface.addr.ip() {
+            IpAddr::V4(addr) if !ipv6 => OutputInterface::V4(addr),
+            IpAddr::V6(addr) if ipv6 && !addr.is_unspecified() => match get_interface_index(&iface.name) {
+                Ok(index) => OutputInterface::V6(index),
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        if !found.iter().any(|(_, known)| *known == interface) {
+            found.push((iface.name, interface));
+        }
+    }
+
+    if interface_preference().prefer_physical && found.iter().any(|(name, _)| !is_virtual_interface(name)) {
+        found.retain(|(name, _)| !is_virtual_interface(name));
+    }
+    Ok(found)
+}
+
+fn open_output(config: &MulticastConfig, group: IpAddr, interface: &OutputInterface) -> io::Result<(Socket, SockAddr)> {
+    let group = SocketAddr::new(group, config.port);
+    let socket = new_socket(&group)?;
+    let destination = match *interface {
+        OutputInterface::V4(addr) => {
+            socket.set_multicast_if_v4(&addr)?;
+            socket.set_multicast_ttl_v4(config.multicast_ttl)?;
+            socket.set_multicast_loop_v4(config.loopback)?;
+            socket.bind(&SockAddr::from(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)))?;
+            group
+        }
+        OutputInterface::V6(index) => {
+            socket.set_multicast_if_v6(index)?;
+            bind_socket_to_ipv6_interface(&socket, index)?;
+            socket.set_multicast_hops_v6(config.multicast_ttl)?;
+            socket.set_multicast_loop_v6(config.loopback)?;
+            socket.bind(&SockAddr::from(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)))?;
+            with_ipv6_scope(group, index)
+        }
+    };
+    Ok((socket, SockAddr::from(destination)))
+}